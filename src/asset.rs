@@ -0,0 +1,29 @@
+/**
+ * Short asset code ("USD", "BTC"). The empty code is the default asset, used
+ * by rows that don't specify one.
+ */
+use crate::{errors::EngineError, short_code};
+
+short_code!(Asset, 8, EngineError::InvalidAsset);
+
+impl Asset {
+    pub fn is_default(&self) -> bool {
+        self.is_empty()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Asset;
+
+    #[test]
+    fn parse_asset() {
+        let btc: Asset = "BTC".parse().unwrap();
+
+        assert_eq!(btc.as_str(), "BTC");
+        assert!(!btc.is_default());
+        assert_eq!("".parse::<Asset>().unwrap(), Asset::default());
+        assert!("TOOLONGCODE".parse::<Asset>().is_err());
+        assert!("US D".parse::<Asset>().is_err());
+    }
+}
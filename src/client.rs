@@ -1,179 +1,891 @@
-use crate::{decimal::Decimal, errors::EngineError};
-use serde::{ser::SerializeStruct, Serialize};
+use crate::{
+    asset::Asset,
+    decimal::Amount,
+    errors::{DecimalError, EngineError},
+    short_code,
+};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
 
 pub type ClientId = u16;
 
-#[derive(Debug)]
-pub struct Client {
+short_code!(Reason, 16, EngineError::InvalidReason);
+
+/**
+ * An account either accepts operations or is locked. Fund operations only
+ * exist on `ActiveClient` and check the compliance freezes themselves;
+ * locking and unlocking consume one state to produce the other. Settling
+ * what is already open, disputes and authorizations, is done on `Client` and
+ * works in both states.
+ */
+#[derive(Debug, Clone)]
+pub enum Client {
+    Active(ActiveClient),
+    Locked(LockedClient),
+}
+
+#[derive(Debug, Clone)]
+pub struct ActiveClient {
+    account: Account,
+}
+
+#[derive(Debug, Clone)]
+pub struct LockedClient {
+    account: Account,
+    /// Why the account was locked, empty for chargebacks
+    reason: Reason,
+}
+
+/// State kept regardless of whether the account is locked
+
+#[derive(Debug, Clone)]
+struct Account {
     id: ClientId,
-    available: Decimal<4>,
-    held: Decimal<4>,
-    locked: bool,
+    balances: BTreeMap<Asset, Funds>,
+    /// How far below zero the available funds of each asset may go
+    credit_limit: Amount,
+    /// Every freeze but `Locked`, which is the state of the client
+    freezes: BTreeMap<Freeze, Reason>,
+    /// Charged back dispute shortfalls per asset, owed by the client
+    receivables: BTreeMap<Asset, Amount>,
+    /// Previous values of what changed since `Client::begin`, if recording
+    undo: Option<Vec<Undo>>,
+}
+
+/// Value something had before a change, to roll it back
+#[derive(Debug, Clone, Copy)]
+enum Undo {
+    /// The balances before, `None` if the asset had none, and the change
+    Funds(Option<Funds>, FundsChange),
+    CreditLimit(Amount),
+    Freeze(Freeze, Option<Reason>),
+    /// The lock reason before, `None` if the client was active
+    Lock(Option<Reason>),
+    Receivable(Asset, Option<Amount>),
+}
+
+/**
+ * Freezes set by admin rows. `Locked` is the `LockedClient` state that a
+ * chargeback moves the account to as well, so that `unlock` and `unfreeze`
+ * rows clear the same lock. Holding funds for a dispute is only blocked by
+ * `Locked` and `Closed`, settling disputes and authorizations by none of them.
+ */
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Freeze {
+    /// No funds may leave the account
+    Debits,
+    /// No funds may enter the account
+    Credits,
+    Locked,
+    Closed,
+}
+
+/// How an operation moves funds, decides which freezes block it
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Direction {
+    Debit,
+    Credit,
+    Internal,
 }
 
-impl Serialize for Client {
-    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
-    where
-        S: serde::Serializer,
-    {
-        let mut s = serializer.serialize_struct("Client", 5)?;
-        s.serialize_field("client", &self.id)?;
-        s.serialize_field("available", &self.available)?;
-        s.serialize_field("held", &self.held)?;
-        s.serialize_field("total", &(self.available + self.held))?;
-        s.serialize_field("locked", &self.locked)?;
-        s.end()
+impl Freeze {
+    fn blocks(&self, direction: Direction) -> bool {
+        match self {
+            Freeze::Debits => direction == Direction::Debit,
+            Freeze::Credits => direction == Direction::Credit,
+            Freeze::Locked | Freeze::Closed => true,
+        }
     }
 }
 
+/// Kinds of fund operations, each one is published as its own event
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Operation {
+    Deposit,
+    Withdrawal,
+    Hold,
+    Release,
+    Chargeback,
+}
+
+/// Balances of one asset before and after a fund operation
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FundsChange {
+    pub operation: Operation,
+    pub asset: Asset,
+    pub before: Funds,
+    pub after: Funds,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub struct Funds {
-    pub available: Decimal<4>,
-    pub held: Decimal<4>,
+    pub available: Amount,
+    pub held: Amount,
+}
+
+impl Default for Funds {
+    fn default() -> Self {
+        Self {
+            available: Amount::zero(),
+            held: Amount::zero(),
+        }
+    }
+}
+
+impl Funds {
+    pub fn total(&self) -> Result<Amount, DecimalError> {
+        self.available.checked_add(self.held)
+    }
+
+    /// Part of the credit line in use
+    pub fn overdraft(&self) -> Result<Amount, DecimalError> {
+        if self.available < Amount::zero() {
+            Amount::zero().checked_sub(self.available)
+        } else {
+            Ok(Amount::zero())
+        }
+    }
+}
+
+impl Account {
+    fn get_funds(&self, asset: Asset) -> Funds {
+        self.balances.get(&asset).copied().unwrap_or_default()
+    }
+
+    fn iter_funds(&self) -> impl Iterator<Item = (Asset, Funds)> + '_ {
+        self.balances.iter().map(|(asset, funds)| (*asset, *funds))
+    }
+
+    fn get_receivable(&self, asset: Asset) -> Amount {
+        let receivable = self.receivables.get(&asset).copied();
+        receivable.unwrap_or(Amount::zero())
+    }
+
+    fn set_funds(&mut self, asset: Asset, funds: Funds, operation: Operation) -> Funds {
+        let before = self.balances.insert(asset, funds);
+        self.record(Undo::Funds(
+            before,
+            FundsChange {
+                operation,
+                asset,
+                before: before.unwrap_or_default(),
+                after: funds,
+            },
+        ));
+        funds
+    }
+
+    fn record(&mut self, undo: Undo) {
+        if let Some(log) = &mut self.undo {
+            log.push(undo);
+        }
+    }
+
+    fn hold(&mut self, asset: Asset, amount: Amount) -> Result<Amount, EngineError> {
+        amount_not_negative(amount)?;
+
+        let mut funds = self.get_funds(asset);
+        funds.available = self.sub(funds.available, amount)?;
+        funds.held = self.add(funds.held, amount)?;
+
+        Ok(self.set_funds(asset, funds, Operation::Hold).available)
+    }
+
+    fn release(&mut self, asset: Asset, amount: Amount) -> Result<Amount, EngineError> {
+        amount_not_negative(amount)?;
+
+        let mut funds = self.get_funds(asset);
+        sufficient_funds(self.id, funds.held, amount)?;
+        funds.available = self.add(funds.available, amount)?;
+        funds.held = self.sub(funds.held, amount)?;
+
+        Ok(self.set_funds(asset, funds, Operation::Release).available)
+    }
+
+    fn take_held(
+        &mut self,
+        asset: Asset,
+        amount: Amount,
+        operation: Operation,
+    ) -> Result<Amount, EngineError> {
+        amount_not_negative(amount)?;
+
+        let mut funds = self.get_funds(asset);
+        sufficient_funds(self.id, funds.held, amount)?;
+        funds.held = self.sub(funds.held, amount)?;
+
+        Ok(self.set_funds(asset, funds, operation).available)
+    }
+
+    fn add(&self, a: Amount, b: Amount) -> Result<Amount, EngineError> {
+        a.checked_add(b).map_err(|_| EngineError::Overflow(self.id))
+    }
+
+    fn sub(&self, a: Amount, b: Amount) -> Result<Amount, EngineError> {
+        a.checked_sub(b).map_err(|_| EngineError::Overflow(self.id))
+    }
+}
+
+fn amount_not_negative(amount: Amount) -> Result<(), EngineError> {
+    if amount < Amount::zero() {
+        Err(EngineError::NegativeAmount(amount))
+    } else {
+        Ok(())
+    }
+}
+
+fn sufficient_funds(id: ClientId, funds: Amount, amount: Amount) -> Result<(), EngineError> {
+    if funds < amount {
+        Err(EngineError::InsufficientFunds(id, funds, amount))
+    } else {
+        Ok(())
+    }
 }
 
 impl Client {
+    /// New accounts start out active, recording changes until the first
+    /// `commit` so that the row creating them can report them
     pub fn new(id: ClientId) -> Self {
-        Self {
-            id: id,
-            available: Decimal::zero(),
-            held: Decimal::zero(),
-            locked: false,
+        let mut client = ActiveClient::new(id);
+        client.account.undo = Some(Vec::new());
+        Client::Active(client)
+    }
+
+    pub fn id(&self) -> ClientId {
+        self.account().id
+    }
+
+    pub fn is_locked(&self) -> bool {
+        matches!(self, Client::Locked(_))
+    }
+
+    pub fn get_funds(&self, asset: Asset) -> Funds {
+        self.account().get_funds(asset)
+    }
+
+    /// Balances ordered by asset, the default asset comes first
+    pub fn iter_funds(&self) -> impl Iterator<Item = (Asset, Funds)> + '_ {
+        self.account().iter_funds()
+    }
+
+    pub fn credit_limit(&self) -> Amount {
+        self.account().credit_limit
+    }
+
+    /// Sets the balances of an asset as saved in a snapshot, skipping every
+    /// check that applies to fund operations
+    pub fn restore_funds(&mut self, asset: Asset, funds: Funds) {
+        self.account_mut().balances.insert(asset, funds);
+    }
+
+    /// Lowering the limit below the overdraft in use only blocks further debits
+    pub fn set_credit_limit(&mut self, limit: Amount) -> Result<(), EngineError> {
+        amount_not_negative(limit)?;
+
+        let account = self.account_mut();
+        let before = std::mem::replace(&mut account.credit_limit, limit);
+        account.record(Undo::CreditLimit(before));
+        Ok(())
+    }
+
+    pub fn get_receivable(&self, asset: Asset) -> Amount {
+        self.account().get_receivable(asset)
+    }
+
+    /// Available funds plus the credit line
+    pub fn spendable_funds(&self, asset: Asset) -> Result<Amount, EngineError> {
+        let account = self.account();
+        account.add(account.get_funds(asset).available, account.credit_limit)
+    }
+
+    /// Receivables ordered by asset
+    pub fn iter_receivables(&self) -> impl Iterator<Item = (Asset, Amount)> + '_ {
+        self.account().receivables.iter().map(|(a, r)| (*a, *r))
+    }
+
+    /// Sets the receivable of an asset as saved in a snapshot
+    pub fn restore_receivable(&mut self, asset: Asset, amount: Amount) {
+        self.account_mut().receivables.insert(asset, amount);
+    }
+
+    /// Records money the client owes without it ever being on the account
+    pub fn add_receivable(&mut self, asset: Asset, amount: Amount) -> Result<Amount, EngineError> {
+        amount_not_negative(amount)?;
+        if amount == Amount::zero() {
+            return Ok(self.get_receivable(asset));
         }
+
+        let account = self.account_mut();
+        let receivable = account.add(account.get_receivable(asset), amount)?;
+        let before = account.receivables.insert(asset, receivable);
+        account.record(Undo::Receivable(asset, before));
+        Ok(receivable)
+    }
+
+    /// Active freezes along with the reason each was set for, the lock
+    /// included
+    pub fn freezes(&self) -> impl Iterator<Item = (Freeze, Reason)> + '_ {
+        let lock = match self {
+            Client::Active(_) => None,
+            Client::Locked(client) => Some((Freeze::Locked, client.reason)),
+        };
+        let mut freezes: Vec<(Freeze, Reason)> = self
+            .account()
+            .freezes
+            .iter()
+            .map(|(f, r)| (*f, *r))
+            .chain(lock)
+            .collect();
+
+        freezes.sort();
+        freezes.into_iter()
+    }
+
+    /// Setting a freeze again replaces its reason
+    pub fn freeze(&mut self, freeze: Freeze, reason: Reason) {
+        if freeze == Freeze::Locked {
+            let before = self.set_lock(Some(reason));
+            self.account_mut().record(Undo::Lock(before));
+            return;
+        }
+
+        let account = self.account_mut();
+        let before = account.freezes.insert(freeze, reason);
+        account.record(Undo::Freeze(freeze, before));
     }
 
-    pub fn get_funds(&self) -> Funds {
-        Funds {
-            available: self.available,
-            held: self.held,
+    pub fn unfreeze(&mut self, freeze: Freeze) -> Result<(), EngineError> {
+        if freeze == Freeze::Locked {
+            if !self.is_locked() {
+                return Err(EngineError::AccountNotFrozen(self.id(), freeze));
+            }
+            let before = self.set_lock(None);
+            self.account_mut().record(Undo::Lock(before));
+            return Ok(());
         }
+
+        let account = self.account_mut();
+        let reason = account
+            .freezes
+            .remove(&freeze)
+            .ok_or(EngineError::AccountNotFrozen(account.id, freeze))?;
+        account.record(Undo::Freeze(freeze, Some(reason)));
+        Ok(())
+    }
+
+    /// Moves the account to the given lock state, returning the one before
+    fn set_lock(&mut self, lock: Option<Reason>) -> Option<Reason> {
+        let placeholder = Client::Active(ActiveClient::new(self.id()));
+        let (client, before) = match std::mem::replace(self, placeholder) {
+            Client::Active(client) => (client, None),
+            Client::Locked(client) => {
+                let reason = client.reason;
+                (client.unlock(), Some(reason))
+            }
+        };
+
+        *self = match lock {
+            Some(reason) => Client::Locked(LockedClient {
+                reason,
+                ..client.lock()
+            }),
+            None => Client::Active(client),
+        };
+        before
+    }
+
+    /// Starts recording changes, so that `rollback` can undo them
+    pub fn begin(&mut self) {
+        self.account_mut().undo = Some(Vec::new());
+    }
+
+    /// Stops recording and keeps the changes
+    pub fn commit(&mut self) {
+        self.account_mut().undo = None;
     }
 
+    /// Undoes every change since `begin` and stops recording
+    pub fn rollback(&mut self) {
+        let undos = self.account_mut().undo.take();
+        for undo in undos.into_iter().flatten().rev() {
+            if let Undo::Lock(lock) = undo {
+                self.set_lock(lock);
+                continue;
+            }
+
+            let account = self.account_mut();
+            match undo {
+                Undo::Funds(Some(funds), change) => {
+                    account.balances.insert(change.asset, funds);
+                }
+                Undo::Funds(None, change) => {
+                    account.balances.remove(&change.asset);
+                }
+                Undo::CreditLimit(limit) => account.credit_limit = limit,
+                Undo::Freeze(freeze, Some(reason)) => {
+                    account.freezes.insert(freeze, reason);
+                }
+                Undo::Freeze(freeze, None) => {
+                    account.freezes.remove(&freeze);
+                }
+                Undo::Receivable(asset, Some(amount)) => {
+                    account.receivables.insert(asset, amount);
+                }
+                Undo::Receivable(asset, None) => {
+                    account.receivables.remove(&asset);
+                }
+                Undo::Lock(_) => {}
+            }
+        }
+    }
+
+    /// Fund operations since `begin`, in the order they ran
+    pub fn changes(&self) -> impl Iterator<Item = FundsChange> + '_ {
+        self.account()
+            .undo
+            .iter()
+            .flatten()
+            .filter_map(|undo| match undo {
+                Undo::Funds(_, change) => Some(*change),
+                _ => None,
+            })
+    }
+
+    /// The account, if it accepts operations
+    pub fn active(&mut self) -> Result<&mut ActiveClient, EngineError> {
+        match self {
+            Client::Active(client) => Ok(client),
+            Client::Locked(client) => Err(EngineError::AccountLocked(client.id())),
+        }
+    }
+
+    /// Locks the account without a reason, as chargebacks do
     pub fn lock(&mut self) -> Result<(), EngineError> {
-        self.not_locked()?;
-        self.locked = true;
+        if self.is_locked() {
+            return Err(EngineError::AccountLocked(self.id()));
+        }
+
+        self.freeze(Freeze::Locked, Reason::default());
         Ok(())
     }
 
-    pub fn deposit_funds(&mut self, amount: Decimal<4>) -> Result<Decimal<4>, EngineError> {
-        self.not_locked()?;
-        Self::amount_not_negative(amount)?;
+    /// Unlocks the account, whatever locked it
+    pub fn unlock(&mut self) -> Result<(), EngineError> {
+        self.unfreeze(Freeze::Locked)
+            .map_err(|_| EngineError::AccountNotLocked(self.id()))
+    }
+
+    /// Held funds go back to the available ones when a dispute is resolved
+    /// or an authorization closed
+    pub fn release_funds(&mut self, asset: Asset, amount: Amount) -> Result<Amount, EngineError> {
+        self.account_mut().release(asset, amount)
+    }
 
-        self.available += amount;
+    /// Held funds leave the account as the outcome of a dispute, e.g. on
+    /// chargebacks
+    pub fn discard_held_funds(
+        &mut self,
+        asset: Asset,
+        amount: Amount,
+    ) -> Result<Amount, EngineError> {
+        self.account_mut()
+            .take_held(asset, amount, Operation::Chargeback)
+    }
+
+    /// Held funds leave the account without a chargeback, e.g. when a
+    /// disputed withdrawal stands
+    pub fn withdraw_held_funds(
+        &mut self,
+        asset: Asset,
+        amount: Amount,
+    ) -> Result<Amount, EngineError> {
+        self.account_mut()
+            .take_held(asset, amount, Operation::Withdrawal)
+    }
 
-        Ok(self.available)
+    /// Charges back funds that were never held, available funds may go below
+    /// the credit line
+    pub fn take_funds(&mut self, asset: Asset, amount: Amount) -> Result<Amount, EngineError> {
+        amount_not_negative(amount)?;
+        let account = self.account_mut();
+
+        let mut funds = account.get_funds(asset);
+        funds.available = account.sub(funds.available, amount)?;
+        Ok(account
+            .set_funds(asset, funds, Operation::Chargeback)
+            .available)
     }
 
-    pub fn withdraw_funds(&mut self, amount: Decimal<4>) -> Result<Decimal<4>, EngineError> {
-        self.not_locked()?;
-        Self::amount_not_negative(amount)?;
-        Self::sufficient_funds(self.id, self.available, amount)?;
+    /// Credits back an outgoing transaction that got charged back
+    pub fn refund_funds(&mut self, asset: Asset, amount: Amount) -> Result<Amount, EngineError> {
+        amount_not_negative(amount)?;
+        let account = self.account_mut();
 
-        self.available -= amount;
+        let mut funds = account.get_funds(asset);
+        funds.available = account.add(funds.available, amount)?;
+        Ok(account
+            .set_funds(asset, funds, Operation::Deposit)
+            .available)
+    }
+
+    /// Held funds leave the account, which gets locked unless it already is
+    pub fn chargeback_funds(
+        &mut self,
+        asset: Asset,
+        amount: Amount,
+    ) -> Result<Amount, EngineError> {
+        let available = self.discard_held_funds(asset, amount)?;
+        if !self.is_locked() {
+            self.lock()?;
+        }
 
-        Ok(self.available)
+        Ok(available)
     }
 
-    pub fn hold_funds(&mut self, amount: Decimal<4>) -> Result<Decimal<4>, EngineError> {
-        self.not_locked()?;
-        Self::amount_not_negative(amount)?;
+    fn account(&self) -> &Account {
+        match self {
+            Client::Active(client) => &client.account,
+            Client::Locked(client) => &client.account,
+        }
+    }
 
-        self.available -= amount;
-        self.held += amount;
+    fn account_mut(&mut self) -> &mut Account {
+        match self {
+            Client::Active(client) => &mut client.account,
+            Client::Locked(client) => &mut client.account,
+        }
+    }
+}
 
-        Ok(self.available)
+impl From<ActiveClient> for Client {
+    fn from(client: ActiveClient) -> Self {
+        Client::Active(client)
     }
+}
 
-    pub fn release_funds(&mut self, amount: Decimal<4>) -> Result<Decimal<4>, EngineError> {
-        self.not_locked()?;
-        Self::amount_not_negative(amount)?;
-        Self::sufficient_funds(self.id, self.held, amount)?;
+impl From<LockedClient> for Client {
+    fn from(client: LockedClient) -> Self {
+        Client::Locked(client)
+    }
+}
 
-        self.available += amount;
-        self.held -= amount;
+impl LockedClient {
+    pub fn id(&self) -> ClientId {
+        self.account.id
+    }
 
-        Ok(self.available)
+    pub fn reason(&self) -> Reason {
+        self.reason
     }
 
-    pub fn chargeback_funds(&mut self, amount: Decimal<4>) -> Result<Decimal<4>, EngineError> {
-        self.not_locked()?;
-        Self::amount_not_negative(amount)?;
-        Self::sufficient_funds(self.id, self.held, amount)?;
+    pub fn get_funds(&self, asset: Asset) -> Funds {
+        self.account.get_funds(asset)
+    }
 
-        self.locked = true;
-        self.held -= amount;
+    pub fn iter_funds(&self) -> impl Iterator<Item = (Asset, Funds)> + '_ {
+        self.account.iter_funds()
+    }
 
-        Ok(self.available)
+    pub fn unlock(self) -> ActiveClient {
+        ActiveClient {
+            account: self.account,
+        }
     }
+}
 
-    fn amount_not_negative(amount: Decimal<4>) -> Result<(), EngineError> {
-        if amount < Decimal::zero() {
-            Err(EngineError::NegativeAmount(amount))
-        } else {
-            Ok(())
+impl ActiveClient {
+    pub fn new(id: ClientId) -> Self {
+        Self {
+            account: Account {
+                id,
+                balances: BTreeMap::new(),
+                credit_limit: Amount::zero(),
+                freezes: BTreeMap::new(),
+                receivables: BTreeMap::new(),
+                undo: None,
+            },
         }
     }
 
-    fn sufficient_funds(
-        id: ClientId,
-        funds: Decimal<4>,
-        amount: Decimal<4>,
-    ) -> Result<(), EngineError> {
-        if funds - amount < Decimal::zero() {
-            Err(EngineError::InsufficientFunds(id, funds, amount))
-        } else {
-            Ok(())
+    pub fn id(&self) -> ClientId {
+        self.account.id
+    }
+
+    pub fn get_funds(&self, asset: Asset) -> Funds {
+        self.account.get_funds(asset)
+    }
+
+    pub fn iter_funds(&self) -> impl Iterator<Item = (Asset, Funds)> + '_ {
+        self.account.iter_funds()
+    }
+
+    pub fn credit_limit(&self) -> Amount {
+        self.account.credit_limit
+    }
+
+    pub fn lock(self) -> LockedClient {
+        LockedClient {
+            account: self.account,
+            reason: Reason::default(),
         }
     }
 
-    fn not_locked(&self) -> Result<(), EngineError> {
-        if self.locked {
-            Err(EngineError::AccountLocked(self.id))
-        } else {
-            Ok(())
+    pub fn deposit_funds(&mut self, asset: Asset, amount: Amount) -> Result<Amount, EngineError> {
+        self.not_frozen(Direction::Credit)?;
+        amount_not_negative(amount)?;
+
+        let mut funds = self.get_funds(asset);
+        funds.available = self.account.add(funds.available, amount)?;
+
+        Ok(self
+            .account
+            .set_funds(asset, funds, Operation::Deposit)
+            .available)
+    }
+
+    /// Available funds may go down to the negative credit limit
+    pub fn withdraw_funds(&mut self, asset: Asset, amount: Amount) -> Result<Amount, EngineError> {
+        self.not_frozen(Direction::Debit)?;
+        amount_not_negative(amount)?;
+
+        let mut funds = self.get_funds(asset);
+        sufficient_funds(self.id(), self.spendable(funds)?, amount)?;
+        funds.available = self.account.sub(funds.available, amount)?;
+
+        Ok(self
+            .account
+            .set_funds(asset, funds, Operation::Withdrawal)
+            .available)
+    }
+
+    /// Not bound by the credit line: a disputed deposit may already be spent
+    pub fn hold_funds(&mut self, asset: Asset, amount: Amount) -> Result<Amount, EngineError> {
+        self.not_frozen(Direction::Internal)?;
+
+        self.account.hold(asset, amount)
+    }
+
+    /// Holds funds only if they can be spent, e.g. for card authorizations
+    pub fn reserve_funds(&mut self, asset: Asset, amount: Amount) -> Result<Amount, EngineError> {
+        self.not_frozen(Direction::Debit)?;
+        sufficient_funds(self.id(), self.spendable(self.get_funds(asset))?, amount)?;
+
+        self.hold_funds(asset, amount)
+    }
+
+    /// Held funds leave the account, e.g. when an authorization is captured
+    pub fn capture_funds(&mut self, asset: Asset, amount: Amount) -> Result<Amount, EngineError> {
+        self.not_frozen(Direction::Debit)?;
+
+        self.account.take_held(asset, amount, Operation::Withdrawal)
+    }
+
+    /// What can still be withdrawn or held, credit line included
+    pub fn spendable_funds(&self, asset: Asset) -> Result<Amount, EngineError> {
+        self.spendable(self.get_funds(asset))
+    }
+
+    fn spendable(&self, funds: Funds) -> Result<Amount, EngineError> {
+        self.account.add(funds.available, self.credit_limit())
+    }
+
+    fn not_frozen(&self, direction: Direction) -> Result<(), EngineError> {
+        match self
+            .account
+            .freezes
+            .iter()
+            .find(|(freeze, _)| freeze.blocks(direction))
+        {
+            Some((freeze, reason)) => Err(EngineError::AccountFrozen(self.id(), *freeze, *reason)),
+            None => Ok(()),
         }
     }
 }
 
 #[cfg(test)]
 mod tests {
-    use super::Client;
-    use crate::decimal::Decimal;
+    use super::{ActiveClient, Client, Freeze, Operation};
+    use crate::{asset::Asset, decimal::Decimal, errors::EngineError};
     use quickcheck::TestResult;
 
     #[quickcheck]
     fn deposit_and_withdraw_equals_zero(amount: u32) -> TestResult {
-        let mut c = Client::new(0);
+        let mut c = ActiveClient::new(0);
         let d = Decimal::<4>::from(amount as i64);
 
-        c.deposit_funds(d).unwrap();
-        c.withdraw_funds(d).unwrap();
+        c.deposit_funds(Asset::default(), d).unwrap();
+        c.withdraw_funds(Asset::default(), d).unwrap();
 
-        TestResult::from_bool(c.get_funds().available == Decimal::zero())
+        TestResult::from_bool(c.get_funds(Asset::default()).available == Decimal::zero())
     }
 
     #[quickcheck]
     fn hold_and_release_equals_zero(amount: u32) -> TestResult {
-        let mut c = Client::new(0);
+        let mut c = ActiveClient::new(0);
         let d = Decimal::<4>::from(amount as i64);
 
-        c.deposit_funds(d).unwrap();
-        c.hold_funds(d).unwrap();
-        c.release_funds(d).unwrap();
+        c.deposit_funds(Asset::default(), d).unwrap();
+        c.hold_funds(Asset::default(), d).unwrap();
+        let mut c = Client::from(c);
+        c.release_funds(Asset::default(), d).unwrap();
 
-        TestResult::from_bool(c.get_funds().held == Decimal::zero() && c.get_funds().available == d)
+        TestResult::from_bool(
+            c.get_funds(Asset::default()).held == Decimal::zero()
+                && c.get_funds(Asset::default()).available == d,
+        )
     }
 
     #[quickcheck]
     fn hold_and_chargeback_equals_zero(amount: u32) -> TestResult {
+        let mut c = ActiveClient::new(0);
+        let d = Decimal::<4>::from(amount as i64);
+
+        c.deposit_funds(Asset::default(), d).unwrap();
+        c.hold_funds(Asset::default(), d).unwrap();
+        let mut c = Client::from(c);
+        c.chargeback_funds(Asset::default(), d).unwrap();
+
+        TestResult::from_bool(
+            c.is_locked()
+                && c.get_funds(Asset::default()).held == Decimal::zero()
+                && c.get_funds(Asset::default()).available == Decimal::zero(),
+        )
+    }
+
+    #[quickcheck]
+    fn overflow_leaves_funds_unchanged(amount: u32) -> TestResult {
+        let mut c = ActiveClient::new(0);
+        let d = Decimal::<4>::from(amount as i64);
+        let max = "900000000000000".parse::<Decimal<4>>().unwrap();
+
+        c.deposit_funds(Asset::default(), max).unwrap();
+        c.hold_funds(Asset::default(), max).unwrap();
+        c.deposit_funds(Asset::default(), max.checked_sub(d).unwrap())
+            .unwrap();
+        let before = c.get_funds(Asset::default());
+
+        let overflows = c.deposit_funds(Asset::default(), max).is_err()
+            && c.hold_funds(Asset::default(), max).is_err();
+        let after = c.get_funds(Asset::default());
+
+        TestResult::from_bool(
+            overflows && before.available == after.available && before.held == after.held,
+        )
+    }
+
+    #[quickcheck]
+    fn assets_are_independent(amount: u32) -> TestResult {
+        let mut c = ActiveClient::new(0);
+        let d = Decimal::<4>::from(amount as i64);
+        let btc = "BTC".parse().unwrap();
+
+        c.deposit_funds(Asset::default(), d).unwrap();
+        c.deposit_funds(btc, d).unwrap();
+        c.hold_funds(btc, d).unwrap();
+
+        TestResult::from_bool(
+            c.get_funds(Asset::default()).available == d
+                && c.get_funds(Asset::default()).held == Decimal::zero()
+                && c.get_funds(btc).held == d
+                && c.withdraw_funds(btc, d.checked_add(Decimal::from(1)).unwrap())
+                    .is_err(),
+        )
+    }
+
+    #[quickcheck]
+    fn unlock_restores_ops(amount: u32) -> TestResult {
+        let mut c = ActiveClient::new(0).lock().unlock();
+        let d = Decimal::<4>::from(amount as i64);
+
+        TestResult::from_bool(
+            c.deposit_funds(Asset::default(), d).is_ok() && Client::from(c).unlock().is_err(),
+        )
+    }
+
+    #[quickcheck]
+    fn reserve_and_capture_equals_zero(amount: u32) -> TestResult {
+        let mut c = ActiveClient::new(0);
+        let d = Decimal::<4>::from(amount as i64);
+
+        c.deposit_funds(Asset::default(), d).unwrap();
+        let overdraft = c.reserve_funds(Asset::default(), d.checked_add(Decimal::from(1)).unwrap());
+        c.reserve_funds(Asset::default(), d).unwrap();
+        c.capture_funds(Asset::default(), d).unwrap();
+
+        TestResult::from_bool(
+            overdraft.is_err()
+                && c.get_funds(Asset::default()).held == Decimal::zero()
+                && c.get_funds(Asset::default()).available == Decimal::zero(),
+        )
+    }
+
+    #[quickcheck]
+    fn withdraw_down_to_credit_limit(amount: u32) -> TestResult {
         let mut c = Client::new(0);
         let d = Decimal::<4>::from(amount as i64);
 
-        c.deposit_funds(d).unwrap();
-        c.hold_funds(d).unwrap();
-        c.chargeback_funds(d).unwrap();
+        c.set_credit_limit(d).unwrap();
+        let c = c.active().unwrap();
+        let beyond = c.withdraw_funds(Asset::default(), d.checked_add(Decimal::from(1)).unwrap());
+        c.withdraw_funds(Asset::default(), d).unwrap();
+        let funds = c.get_funds(Asset::default());
+
+        TestResult::from_bool(
+            beyond.is_err()
+                && funds.available == Decimal::from(-(amount as i64))
+                && funds.overdraft() == Ok(d)
+                && c.reserve_funds(Asset::default(), Decimal::from(1)).is_err(),
+        )
+    }
+
+    #[quickcheck]
+    fn debit_freeze_allows_credits(amount: u32) -> TestResult {
+        let mut c = Client::new(0);
+        let d = Decimal::<4>::from(amount as i64);
 
+        c.freeze(Freeze::Debits, "AML".parse().unwrap());
+        let active = c.active().unwrap();
+        active.deposit_funds(Asset::default(), d).unwrap();
+        active.hold_funds(Asset::default(), d).unwrap();
+        let frozen = matches!(
+            active.withdraw_funds(Asset::default(), d),
+            Err(EngineError::AccountFrozen(0, Freeze::Debits, _))
+        ) && active.reserve_funds(Asset::default(), d).is_err();
+        c.release_funds(Asset::default(), d).unwrap();
+
+        c.unfreeze(Freeze::Debits).unwrap();
         TestResult::from_bool(
-            c.get_funds().held == Decimal::zero() && c.get_funds().available == Decimal::zero(),
+            frozen
+                && c.active()
+                    .unwrap()
+                    .withdraw_funds(Asset::default(), d)
+                    .is_ok()
+                && c.unfreeze(Freeze::Debits).is_err(),
+        )
+    }
+
+    /// Only settling what is already open goes through
+    #[quickcheck]
+    fn full_freeze_blocks_everything(amount: u32) -> TestResult {
+        let mut c = Client::new(0);
+        let d = Decimal::<4>::from(amount as i64);
+
+        c.active()
+            .unwrap()
+            .deposit_funds(Asset::default(), d)
+            .unwrap();
+        c.freeze(Freeze::Credits, "SANCTION".parse().unwrap());
+        let credit = c.active().unwrap().deposit_funds(Asset::default(), d);
+        c.freeze(Freeze::Closed, "CLOSED".parse().unwrap());
+        let active = c.active().unwrap();
+
+        TestResult::from_bool(
+            credit.is_err()
+                && active.hold_funds(Asset::default(), d).is_err()
+                && active.withdraw_funds(Asset::default(), d).is_err()
+                && c.chargeback_funds(Asset::default(), Decimal::zero())
+                    .is_ok(),
         )
     }
 
@@ -182,10 +894,74 @@ mod tests {
         let mut c = Client::new(0);
 
         c.lock().unwrap();
-        if let Err(_) = c.deposit_funds(Decimal::zero()) {
-            TestResult::passed()
-        } else {
-            TestResult::failed()
+        match c.active() {
+            Err(EngineError::AccountLocked(0)) => TestResult::from_bool(c.lock().is_err()),
+            _ => TestResult::failed(),
         }
     }
+
+    #[test]
+    fn rollback_undoes_changes() {
+        let mut c = Client::new(0);
+        let btc = "BTC".parse().unwrap();
+        c.active()
+            .unwrap()
+            .deposit_funds(Asset::default(), Decimal::from(10))
+            .unwrap();
+
+        c.begin();
+        let active = c.active().unwrap();
+        active
+            .withdraw_funds(Asset::default(), Decimal::from(4))
+            .unwrap();
+        active.deposit_funds(btc, Decimal::from(1)).unwrap();
+        active
+            .hold_funds(Asset::default(), Decimal::from(6))
+            .unwrap();
+        c.set_credit_limit(Decimal::from(5)).unwrap();
+        c.lock().unwrap();
+        let operations: Vec<Operation> = c.changes().map(|c| c.operation).collect();
+        assert_eq!(
+            operations,
+            vec![Operation::Withdrawal, Operation::Deposit, Operation::Hold]
+        );
+        c.rollback();
+
+        assert_eq!(c.get_funds(Asset::default()).available, Decimal::from(10));
+        assert_eq!(c.get_funds(Asset::default()).held, Decimal::zero());
+        assert_eq!(c.iter_funds().count(), 1);
+        assert_eq!(c.credit_limit(), Decimal::zero());
+        assert!(!c.is_locked());
+    }
+
+    #[test]
+    fn lock_is_a_freeze() {
+        let mut c = Client::new(0);
+
+        let review = "REVIEW".parse().unwrap();
+        c.freeze(Freeze::Locked, review);
+        assert!(matches!(&c, Client::Locked(locked) if locked.reason() == review));
+        assert_eq!(c.lock(), Err(EngineError::AccountLocked(0)));
+
+        // Rolling back an unlock brings back the lock and its reason
+        c.begin();
+        c.unfreeze(Freeze::Locked).unwrap();
+        assert!(c.active().is_ok());
+        c.rollback();
+        assert_eq!(
+            c.freezes().collect::<Vec<_>>(),
+            vec![(Freeze::Locked, review)]
+        );
+
+        c.unfreeze(Freeze::Locked).unwrap();
+        assert!(matches!(c, Client::Active(_)));
+
+        c.lock().unwrap();
+        assert_eq!(c.freezes().count(), 1);
+        c.unlock().unwrap();
+        assert_eq!(
+            c.unfreeze(Freeze::Locked),
+            Err(EngineError::AccountNotFrozen(0, Freeze::Locked))
+        );
+    }
 }
@@ -0,0 +1,70 @@
+/**
+ * Short alphanumeric codes (asset codes, reason codes) stored inline so that
+ * the types holding them can stay `Copy`. Besides letters and digits, `-`
+ * and `_` are accepted.
+ */
+#[macro_export]
+macro_rules! short_code {
+    ( $name:ident, $len:expr, $error:expr) => {
+        #[derive(Debug, Copy, Clone, Default, PartialEq, Eq, Hash, PartialOrd, Ord)]
+        pub struct $name {
+            code: [u8; $len],
+            len: u8,
+        }
+
+        impl $name {
+            pub fn as_str(&self) -> &str {
+                // Only ASCII characters are ever stored
+                std::str::from_utf8(&self.code[..self.len as usize]).unwrap_or_default()
+            }
+
+            pub fn is_empty(&self) -> bool {
+                self.len == 0
+            }
+        }
+
+        impl std::str::FromStr for $name {
+            type Err = $crate::errors::EngineError;
+
+            fn from_str(s: &str) -> Result<Self, Self::Err> {
+                let valid = |b: u8| b.is_ascii_alphanumeric() || b == b'-' || b == b'_';
+                if s.len() > $len || !s.bytes().all(valid) {
+                    return Err($error);
+                }
+
+                let mut code = [0; $len];
+                code[..s.len()].copy_from_slice(s.as_bytes());
+
+                Ok(Self {
+                    code,
+                    len: s.len() as u8,
+                })
+            }
+        }
+
+        impl std::fmt::Display for $name {
+            fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+                f.write_str(self.as_str())
+            }
+        }
+
+        impl serde::Serialize for $name {
+            fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+            where
+                S: serde::Serializer,
+            {
+                serializer.serialize_str(self.as_str())
+            }
+        }
+
+        impl<'de> serde::Deserialize<'de> for $name {
+            fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+            where
+                D: serde::Deserializer<'de>,
+            {
+                let s = <std::borrow::Cow<str>>::deserialize(deserializer)?;
+                s.parse().map_err(serde::de::Error::custom)
+            }
+        }
+    };
+}
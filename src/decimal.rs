@@ -1,24 +1,61 @@
 /**
- * Simple implementation of fixed point numbers. Overflows are not handled
- * as we assume that no one would have assets close to 2^63.
+ * Simple implementation of fixed point numbers. There are no arithmetic
+ * operators, the `checked_*` methods report overflows as errors.
  */
-use serde::{Deserialize, Serialize};
-use std::{
-    fmt::Display,
-    ops::{Add, AddAssign, Sub, SubAssign},
-};
+use crate::errors::DecimalError;
+pub use rounding::{Residue, Rounded, RoundingMode};
+pub use scaled::ScaledDecimal;
+use serde::{de::Visitor, Deserialize, Serialize};
+use std::{fmt::Display, str::FromStr};
+pub use wide::WideDecimal;
+
+mod rounding;
+mod scaled;
+mod wide;
+
+pub const AMOUNT_PRECISION: u32 = 4;
+
+/// Precision used for all the balances kept by the engine
+pub type Amount = Decimal<AMOUNT_PRECISION>;
+/// Used for totals over many balances
+pub type WideAmount = WideDecimal<AMOUNT_PRECISION>;
 
 #[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord)]
 pub struct Decimal<const PRECISION: u32> {
     n: i64,
 }
 
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Default)]
+pub enum DecimalFormat {
+    /// Drops trailing zeros, keeping at least one fraction digit ("1.5")
+    #[default]
+    Trimmed,
+    /// Always prints all the fraction digits ("1.5000")
+    FullScale,
+}
+
+impl FromStr for DecimalFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "trimmed" => Ok(DecimalFormat::Trimmed),
+            "full" => Ok(DecimalFormat::FullScale),
+            _ => Err(format!(
+                "Unknown amount format {}, expected trimmed or full.",
+                s
+            )),
+        }
+    }
+}
+
+/// Always trimmed, reports pick their format through `format`
 impl<const PRECISION: u32> Serialize for Decimal<PRECISION> {
     fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
     where
         S: serde::Serializer,
     {
-        serializer.serialize_newtype_struct("Decimal", &Into::<f64>::into(*self))
+        serializer.serialize_str(&self.format(DecimalFormat::Trimmed))
     }
 }
 
@@ -27,23 +64,122 @@ impl<'de, const PRECISION: u32> Deserialize<'de> for Decimal<PRECISION> {
     where
         D: serde::Deserializer<'de>,
     {
-        let s: &str = Deserialize::deserialize(deserializer)?;
-        if let Ok(n) = s.parse::<f64>() {
-            Ok(Decimal::from(n))
-        } else {
-            Ok(Decimal::zero())
-        }
+        deserializer.deserialize_str(DecimalVisitor::<PRECISION>)
+    }
+}
+
+struct DecimalVisitor<const PRECISION: u32>;
+
+impl<'de, const PRECISION: u32> Visitor<'de> for DecimalVisitor<PRECISION> {
+    type Value = Decimal<PRECISION>;
+
+    fn expecting(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(
+            f,
+            "a decimal number with at most {} fraction digits",
+            PRECISION
+        )
+    }
+
+    fn visit_str<E>(self, s: &str) -> Result<Self::Value, E>
+    where
+        E: serde::de::Error,
+    {
+        s.parse().map_err(E::custom)
+    }
+}
+
+/**
+ * Exact parser for strings like "-12.3400". Trailing zeros past the precision
+ * are accepted since they don't change the value.
+ */
+impl<const PRECISION: u32> FromStr for Decimal<PRECISION> {
+    type Err = DecimalError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let n = parse_fixed(s, PRECISION)?;
+
+        Ok(Self {
+            n: i64::try_from(n).map_err(|_| DecimalError::OutOfRange)?,
+        })
+    }
+}
+
+fn parse_fixed(s: &str, precision: u32) -> Result<i128, DecimalError> {
+    let (negative, digits) = match s.as_bytes().first() {
+        Some(b'-') => (true, &s[1..]),
+        Some(b'+') => (false, &s[1..]),
+        _ => (false, s),
+    };
+    let (integer, fraction) = digits.split_once('.').unwrap_or((digits, ""));
+
+    if integer.is_empty() && fraction.is_empty() {
+        return Err(DecimalError::Malformed);
+    }
+    if !integer
+        .bytes()
+        .chain(fraction.bytes())
+        .all(|b| b.is_ascii_digit())
+    {
+        return Err(DecimalError::Malformed);
     }
+
+    let significant = fraction.trim_end_matches('0');
+    if significant.len() > precision as usize {
+        return Err(DecimalError::TooManyFractionDigits(precision));
+    }
+
+    let n = integer
+        .bytes()
+        .chain(significant.bytes())
+        .chain(std::iter::repeat_n(
+            b'0',
+            precision as usize - significant.len(),
+        ))
+        .try_fold(0i128, |acc, digit| {
+            acc.checked_mul(10)?.checked_add((digit - b'0') as i128)
+        })
+        .ok_or(DecimalError::OutOfRange)?;
+
+    Ok(if negative { -n } else { n })
 }
 
 impl<const PRECISION: u32> Decimal<PRECISION> {
     pub const fn zero() -> Self {
         Self { n: 0 }
     }
+
+    pub fn format(&self, format: DecimalFormat) -> String {
+        format_fixed(self.n as i128, PRECISION, format)
+    }
+}
+
+fn format_fixed(n: i128, precision: u32, format: DecimalFormat) -> String {
+    let scale = 10_u128.pow(precision);
+    let sign = if n < 0 { "-" } else { "" };
+    let integer = n.unsigned_abs() / scale;
+    if precision == 0 {
+        return format!("{}{}", sign, integer);
+    }
+
+    let fraction = format!(
+        "{:0width$}",
+        n.unsigned_abs() % scale,
+        width = precision as usize
+    );
+    let fraction = match format {
+        DecimalFormat::Trimmed => match fraction.trim_end_matches('0') {
+            "" => "0",
+            trimmed => trimmed,
+        },
+        DecimalFormat::FullScale => &fraction,
+    };
+
+    format!("{}{}.{}", sign, integer, fraction)
 }
 
 const fn ten_pow(n: u32) -> i64 {
-    (10 as i64).pow(n)
+    10_i64.pow(n)
 }
 
 impl<const PRECISION: u32> From<f64> for Decimal<PRECISION> {
@@ -62,57 +198,49 @@ impl<const PRECISION: u32> From<i64> for Decimal<PRECISION> {
     }
 }
 
-impl<const PRECISION: u32> Into<f64> for Decimal<PRECISION> {
-    fn into(self) -> f64 {
-        self.n as f64 / ten_pow(PRECISION) as f64
+impl<const PRECISION: u32> From<Decimal<PRECISION>> for f64 {
+    fn from(d: Decimal<PRECISION>) -> f64 {
+        d.n as f64 / ten_pow(PRECISION) as f64
     }
 }
 
+/**
+ * Prints the trimmed representation, the alternate flag (`{:#}`) prints
+ * the full scale.
+ */
 impl<const PRECISION: u32> Display for Decimal<PRECISION> {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        write!(
-            f,
-            "{}.{}",
-            self.n / ten_pow(PRECISION),
-            self.n % ten_pow(PRECISION)
-        )
-    }
-}
+        let format = if f.alternate() {
+            DecimalFormat::FullScale
+        } else {
+            DecimalFormat::Trimmed
+        };
 
-#[macro_export]
-macro_rules! basic_op {
-    ( $name:ident, $op:ident, $method:ident) => {
-        impl<const PRECISION: u32> $op for $name<PRECISION> {
-            type Output = Self;
-
-            fn $method(self, rhs: Self) -> Self::Output {
-                Self {
-                    n: self.n.$method(rhs.n),
-                }
-            }
-        }
-    };
+        f.write_str(&self.format(format))
+    }
 }
 
 #[macro_export]
-macro_rules! basic_assign_op {
-    ( $name:ident, $op:ident, $method:ident) => {
-        impl<const PRECISION: u32> $op for $name<PRECISION> {
-            fn $method(&mut self, rhs: Self) {
-                self.n.$method(rhs.n);
+macro_rules! checked_op {
+    ( $name:ident, $method:ident) => {
+        impl<const PRECISION: u32> $name<PRECISION> {
+            pub fn $method(self, rhs: Self) -> Result<Self, $crate::errors::DecimalError> {
+                self.n
+                    .$method(rhs.n)
+                    .map(|n| Self { n })
+                    .ok_or($crate::errors::DecimalError::Overflow)
             }
         }
     };
 }
 
-basic_op!(Decimal, Add, add);
-basic_op!(Decimal, Sub, sub);
-basic_assign_op!(Decimal, AddAssign, add_assign);
-basic_assign_op!(Decimal, SubAssign, sub_assign);
+checked_op!(Decimal, checked_add);
+checked_op!(Decimal, checked_sub);
 
 #[cfg(test)]
 mod tests {
-    use super::Decimal;
+    use super::{Decimal, DecimalFormat};
+    use crate::errors::DecimalError;
 
     #[test]
     fn into_float() {
@@ -122,14 +250,82 @@ mod tests {
 
     #[test]
     fn ops_precision() {
-        let mut res =
-            Decimal::<3>::from(1.250) + Decimal::<3>::from(3.350) - Decimal::<3>::from(2.150);
-        res += Decimal::<3>::from(1);
+        let res = Decimal::<3>::from(1.250)
+            .checked_add(Decimal::<3>::from(3.350))
+            .and_then(|r| r.checked_sub(Decimal::<3>::from(2.150)))
+            .and_then(|r| r.checked_add(Decimal::<3>::from(1)))
+            .unwrap();
         assert_eq!(Into::<f64>::into(res), 3.450);
     }
 
+    #[test]
+    fn checked_ops() {
+        let max = Decimal::<2> { n: i64::MAX };
+        let min = Decimal::<2> { n: i64::MIN };
+        let one = Decimal::<2>::from(1);
+
+        assert_eq!(max.checked_add(one), Err(DecimalError::Overflow));
+        assert_eq!(min.checked_sub(one), Err(DecimalError::Overflow));
+        assert_eq!(max.checked_sub(one), Ok(Decimal { n: i64::MAX - 100 }));
+    }
+
+    #[test]
+    fn parse_exact() {
+        assert_eq!("0.3".parse::<Decimal<4>>().unwrap().n, 3000);
+        assert_eq!("-1.25".parse::<Decimal<2>>().unwrap().n, -125);
+        assert_eq!("+.5".parse::<Decimal<2>>().unwrap().n, 50);
+        assert_eq!("7.".parse::<Decimal<2>>().unwrap().n, 700);
+        assert_eq!("1.2500".parse::<Decimal<2>>().unwrap().n, 125);
+        assert_eq!(
+            "9007199254740993".parse::<Decimal<0>>().unwrap().n,
+            9007199254740993
+        );
+    }
+
+    #[test]
+    fn parse_errors() {
+        assert_eq!("".parse::<Decimal<2>>(), Err(DecimalError::Malformed));
+        assert_eq!(".".parse::<Decimal<2>>(), Err(DecimalError::Malformed));
+        assert_eq!("1.2.3".parse::<Decimal<2>>(), Err(DecimalError::Malformed));
+        assert_eq!("1e5".parse::<Decimal<2>>(), Err(DecimalError::Malformed));
+        assert_eq!(
+            "1.234".parse::<Decimal<2>>(),
+            Err(DecimalError::TooManyFractionDigits(2))
+        );
+        assert_eq!(
+            "100000000000000000".parse::<Decimal<4>>(),
+            Err(DecimalError::OutOfRange)
+        );
+    }
+
     #[test]
     fn format() {
         assert_eq!(format!("{}", Decimal::<3>::from(1.2349)), "1.234");
+        assert_eq!(format!("{}", "1.05".parse::<Decimal<3>>().unwrap()), "1.05");
+        assert_eq!(format!("{}", "-0.5".parse::<Decimal<4>>().unwrap()), "-0.5");
+        assert_eq!(format!("{}", Decimal::<4>::zero()), "0.0");
+        assert_eq!(format!("{}", "-12".parse::<Decimal<0>>().unwrap()), "-12");
+        assert_eq!(
+            Decimal::<4> { n: i64::MIN }.to_string(),
+            "-922337203685477.5808"
+        );
+    }
+
+    #[test]
+    fn format_full_scale() {
+        assert_eq!(
+            format!("{:#}", "1.05".parse::<Decimal<4>>().unwrap()),
+            "1.0500"
+        );
+        assert_eq!(
+            format!("{:#}", "-0.5".parse::<Decimal<4>>().unwrap()),
+            "-0.5000"
+        );
+        assert_eq!(
+            "3".parse::<Decimal<2>>()
+                .unwrap()
+                .format(DecimalFormat::FullScale),
+            "3.00"
+        );
     }
 }
@@ -0,0 +1,292 @@
+/**
+ * Multiplication and division for fixed point numbers. Results that can't be
+ * represented exactly are rounded with an explicit mode and the part that was
+ * rounded away is reported back, so no fraction of a unit gets lost.
+ */
+use super::Decimal;
+use crate::errors::DecimalError;
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum RoundingMode {
+    /// Round to nearest, ties away from zero
+    HalfUp,
+    /// Round to nearest, ties to the even neighbour (banker's rounding)
+    HalfEven,
+    /// Round towards negative infinity
+    Floor,
+    /// Round towards positive infinity
+    Ceiling,
+    /// Round towards zero
+    Truncate,
+}
+
+/**
+ * What was left out by rounding, measured in units of the last digit of the
+ * result: `exact = value + numerator / denominator`. The denominator is
+ * always positive.
+ */
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct Residue {
+    pub numerator: i128,
+    pub denominator: i128,
+}
+
+impl Residue {
+    pub fn is_zero(&self) -> bool {
+        self.numerator == 0
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct Rounded<T> {
+    pub value: T,
+    pub residue: Residue,
+}
+
+impl<const PRECISION: u32> Decimal<PRECISION> {
+    /// Exact, multiplying by an integer never needs rounding.
+    pub fn checked_mul_int(self, rhs: i64) -> Result<Self, DecimalError> {
+        self.n
+            .checked_mul(rhs)
+            .map(|n| Self { n })
+            .ok_or(DecimalError::Overflow)
+    }
+
+    pub fn checked_div_int(
+        self,
+        rhs: i64,
+        mode: RoundingMode,
+    ) -> Result<Rounded<Self>, DecimalError> {
+        Self::rounded(self.n as i128, rhs as i128, mode)
+    }
+
+    /// Also used for rates, which are decimals with their own precision.
+    pub fn checked_mul<const Q: u32>(
+        self,
+        rhs: Decimal<Q>,
+        mode: RoundingMode,
+    ) -> Result<Rounded<Self>, DecimalError> {
+        Self::rounded(self.n as i128 * rhs.n as i128, scale(Q)?, mode)
+    }
+
+    pub fn checked_div<const Q: u32>(
+        self,
+        rhs: Decimal<Q>,
+        mode: RoundingMode,
+    ) -> Result<Rounded<Self>, DecimalError> {
+        let numerator = (self.n as i128)
+            .checked_mul(scale(Q)?)
+            .ok_or(DecimalError::Overflow)?;
+
+        Self::rounded(numerator, rhs.n as i128, mode)
+    }
+
+    /// Converts to another precision, rounding only when the scale shrinks.
+    pub fn rescale<const Q: u32>(
+        self,
+        mode: RoundingMode,
+    ) -> Result<Rounded<Decimal<Q>>, DecimalError> {
+        let (n, residue) = rescale(self.n as i128, PRECISION, Q, mode)?;
+        let n = i64::try_from(n).map_err(|_| DecimalError::Overflow)?;
+
+        Ok(Rounded {
+            value: Decimal { n },
+            residue,
+        })
+    }
+
+    fn rounded(
+        numerator: i128,
+        denominator: i128,
+        mode: RoundingMode,
+    ) -> Result<Rounded<Self>, DecimalError> {
+        let (n, residue) = divide(numerator, denominator, mode)?;
+        let n = i64::try_from(n).map_err(|_| DecimalError::Overflow)?;
+
+        Ok(Rounded {
+            value: Self { n },
+            residue,
+        })
+    }
+}
+
+pub(super) fn scale(precision: u32) -> Result<i128, DecimalError> {
+    10_i128.checked_pow(precision).ok_or(DecimalError::Overflow)
+}
+
+pub(super) fn rescale(
+    n: i128,
+    from: u32,
+    to: u32,
+    mode: RoundingMode,
+) -> Result<(i128, Residue), DecimalError> {
+    if to >= from {
+        let n = n
+            .checked_mul(scale(to - from)?)
+            .ok_or(DecimalError::Overflow)?;
+
+        Ok((
+            n,
+            Residue {
+                numerator: 0,
+                denominator: 1,
+            },
+        ))
+    } else {
+        divide(n, scale(from - to)?, mode)
+    }
+}
+
+pub(super) fn divide(
+    numerator: i128,
+    denominator: i128,
+    mode: RoundingMode,
+) -> Result<(i128, Residue), DecimalError> {
+    if denominator == 0 {
+        return Err(DecimalError::DivisionByZero);
+    }
+
+    let (numerator, denominator) = if denominator < 0 {
+        (
+            numerator.checked_neg().ok_or(DecimalError::Overflow)?,
+            denominator.checked_neg().ok_or(DecimalError::Overflow)?,
+        )
+    } else {
+        (numerator, denominator)
+    };
+
+    // floor division, 0 <= remainder < denominator
+    let quotient = numerator.div_euclid(denominator);
+    let remainder = numerator.rem_euclid(denominator);
+    let half = remainder.cmp(&(denominator - remainder));
+
+    let round_up = remainder != 0
+        && match mode {
+            RoundingMode::Floor => false,
+            RoundingMode::Ceiling => true,
+            RoundingMode::Truncate => numerator < 0,
+            RoundingMode::HalfUp => half.is_gt() || (half.is_eq() && numerator >= 0),
+            RoundingMode::HalfEven => half.is_gt() || (half.is_eq() && quotient % 2 != 0),
+        };
+
+    let (quotient, remainder) = if round_up {
+        (quotient + 1, remainder - denominator)
+    } else {
+        (quotient, remainder)
+    };
+
+    Ok((
+        quotient,
+        Residue {
+            numerator: remainder,
+            denominator,
+        },
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Residue, RoundingMode};
+    use crate::{decimal::Decimal, errors::DecimalError};
+
+    fn d(s: &str) -> Decimal<2> {
+        s.parse().unwrap()
+    }
+
+    #[test]
+    fn rounding_modes() {
+        let cases = [
+            ("0.25", ["0.13", "0.12", "0.12", "0.13", "0.12"]),
+            ("0.35", ["0.18", "0.18", "0.17", "0.18", "0.17"]),
+            ("-0.25", ["-0.13", "-0.12", "-0.13", "-0.12", "-0.12"]),
+            ("0.07", ["0.04", "0.04", "0.03", "0.04", "0.03"]),
+        ];
+        let modes = [
+            RoundingMode::HalfUp,
+            RoundingMode::HalfEven,
+            RoundingMode::Floor,
+            RoundingMode::Ceiling,
+            RoundingMode::Truncate,
+        ];
+
+        for (input, expected) in cases {
+            for (mode, expected) in modes.iter().zip(expected) {
+                let rounded = d(input).checked_div_int(2, *mode).unwrap();
+                assert_eq!(rounded.value, d(expected), "{} / 2 ({:?})", input, mode);
+            }
+        }
+    }
+
+    #[test]
+    fn residue_accounts_for_everything() {
+        let third = d("100").checked_div_int(3, RoundingMode::HalfEven).unwrap();
+
+        assert_eq!(third.value, d("33.33"));
+        assert_eq!(
+            third.residue,
+            Residue {
+                numerator: 1,
+                denominator: 3
+            }
+        );
+    }
+
+    #[test]
+    fn multiply_by_rate() {
+        let rate: Decimal<4> = "0.0125".parse().unwrap();
+        let fee = d("10.10").checked_mul(rate, RoundingMode::HalfUp).unwrap();
+
+        // 10.10 * 0.0125 = 0.12625
+        assert_eq!(fee.value, d("0.13"));
+        assert_eq!(
+            fee.residue,
+            Residue {
+                numerator: -3750,
+                denominator: 10000
+            }
+        );
+        assert_eq!(d("1.5").checked_mul_int(3), Ok(d("4.5")));
+    }
+
+    #[test]
+    fn rescale_between_precisions() {
+        let wide: Decimal<8> = d("1.25").rescale(RoundingMode::Floor).unwrap().value;
+        assert_eq!(wide, "1.25".parse().unwrap());
+
+        let narrow = d("1.25").rescale::<1>(RoundingMode::HalfEven).unwrap();
+        assert_eq!(narrow.value, "1.2".parse().unwrap());
+        assert_eq!(
+            narrow.residue,
+            Residue {
+                numerator: 5,
+                denominator: 10
+            }
+        );
+
+        let whole = d("-1.5").rescale::<0>(RoundingMode::HalfUp).unwrap();
+        assert_eq!(whole.value, "-2".parse().unwrap());
+        assert_eq!(
+            d("90000000000000000").rescale::<4>(RoundingMode::Floor),
+            Err(DecimalError::Overflow)
+        );
+    }
+
+    #[test]
+    fn divide_by_decimal() {
+        let share = d("10").checked_div(d("3"), RoundingMode::Truncate).unwrap();
+
+        assert_eq!(share.value, d("3.33"));
+        assert!(!share.residue.is_zero());
+        assert_eq!(
+            d("1").checked_div(d("0"), RoundingMode::Floor),
+            Err(DecimalError::DivisionByZero)
+        );
+        assert_eq!(
+            d("1")
+                .checked_div_int(-4, RoundingMode::Floor)
+                .unwrap()
+                .value,
+            d("-0.25")
+        );
+    }
+}
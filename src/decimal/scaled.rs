@@ -0,0 +1,148 @@
+/**
+ * Fixed point number whose precision is only known at runtime (e.g. it comes
+ * from the asset configuration). Operations between numbers of different
+ * scales are rejected, use `rescale` to align them first. Backed by `i128`
+ * so that even 18 fraction digits leave enough room for the integer part.
+ */
+use super::{format_fixed, parse_fixed, rounding, Decimal, DecimalFormat, Rounded, RoundingMode};
+use crate::errors::DecimalError;
+use std::fmt::Display;
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct ScaledDecimal {
+    n: i128,
+    scale: u32,
+}
+
+impl ScaledDecimal {
+    pub const fn zero(scale: u32) -> Self {
+        Self { n: 0, scale }
+    }
+
+    pub fn scale(&self) -> u32 {
+        self.scale
+    }
+
+    pub fn parse(s: &str, scale: u32) -> Result<Self, DecimalError> {
+        Ok(Self {
+            n: parse_fixed(s, scale)?,
+            scale,
+        })
+    }
+
+    pub fn format(&self, format: DecimalFormat) -> String {
+        format_fixed(self.n, self.scale, format)
+    }
+
+    pub fn checked_add(self, rhs: Self) -> Result<Self, DecimalError> {
+        self.same_scale(rhs)?;
+        self.n
+            .checked_add(rhs.n)
+            .map(|n| Self { n, ..self })
+            .ok_or(DecimalError::Overflow)
+    }
+
+    pub fn checked_sub(self, rhs: Self) -> Result<Self, DecimalError> {
+        self.same_scale(rhs)?;
+        self.n
+            .checked_sub(rhs.n)
+            .map(|n| Self { n, ..self })
+            .ok_or(DecimalError::Overflow)
+    }
+
+    pub fn rescale(self, scale: u32, mode: RoundingMode) -> Result<Rounded<Self>, DecimalError> {
+        let (n, residue) = rounding::rescale(self.n, self.scale, scale, mode)?;
+
+        Ok(Rounded {
+            value: Self { n, scale },
+            residue,
+        })
+    }
+
+    fn same_scale(&self, other: Self) -> Result<(), DecimalError> {
+        if self.scale == other.scale {
+            Ok(())
+        } else {
+            Err(DecimalError::ScaleMismatch(self.scale, other.scale))
+        }
+    }
+}
+
+impl Display for ScaledDecimal {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let format = if f.alternate() {
+            DecimalFormat::FullScale
+        } else {
+            DecimalFormat::Trimmed
+        };
+
+        f.write_str(&self.format(format))
+    }
+}
+
+impl<const PRECISION: u32> From<Decimal<PRECISION>> for ScaledDecimal {
+    fn from(d: Decimal<PRECISION>) -> Self {
+        Self {
+            n: d.n as i128,
+            scale: PRECISION,
+        }
+    }
+}
+
+/// Only exact conversions are allowed, rounding has to be done explicitly.
+impl<const PRECISION: u32> TryFrom<ScaledDecimal> for Decimal<PRECISION> {
+    type Error = DecimalError;
+
+    fn try_from(d: ScaledDecimal) -> Result<Self, Self::Error> {
+        let rounded = d.rescale(PRECISION, RoundingMode::Truncate)?;
+        if !rounded.residue.is_zero() {
+            return Err(DecimalError::TooManyFractionDigits(PRECISION));
+        }
+
+        Ok(Decimal {
+            n: i64::try_from(rounded.value.n).map_err(|_| DecimalError::Overflow)?,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::ScaledDecimal;
+    use crate::{
+        decimal::{Decimal, RoundingMode},
+        errors::DecimalError,
+    };
+
+    #[test]
+    fn runtime_scale() {
+        let a = ScaledDecimal::parse("1.5", 8).unwrap();
+        let b = ScaledDecimal::parse("0.00000001", 8).unwrap();
+
+        assert_eq!(a.checked_add(b).unwrap().to_string(), "1.50000001");
+        assert_eq!(format!("{:#}", ScaledDecimal::zero(2)), "0.00");
+        assert_eq!(
+            a.checked_sub(ScaledDecimal::parse("1", 2).unwrap()),
+            Err(DecimalError::ScaleMismatch(8, 2))
+        );
+    }
+
+    #[test]
+    fn convert_from_and_to_decimal() {
+        let d: Decimal<2> = "12.34".parse().unwrap();
+        let scaled = ScaledDecimal::from(d)
+            .rescale(18, RoundingMode::Floor)
+            .unwrap()
+            .value;
+
+        assert_eq!(scaled.scale(), 18);
+        assert_eq!(Decimal::<4>::try_from(scaled), Ok("12.34".parse().unwrap()));
+        assert_eq!(
+            Decimal::<1>::try_from(scaled),
+            Err(DecimalError::TooManyFractionDigits(1))
+        );
+        assert_eq!(
+            Decimal::<4>::try_from(ScaledDecimal::parse("1000000000000000", 0).unwrap()),
+            Err(DecimalError::Overflow)
+        );
+    }
+}
@@ -0,0 +1,120 @@
+/**
+ * Fixed point number backed by `i128`, meant for aggregates (ledger totals,
+ * reconciliation sums) that could overflow a regular `Decimal`. Widening from
+ * `Decimal` is lossless while narrowing back is checked.
+ */
+use super::{format_fixed, parse_fixed, Decimal, DecimalFormat};
+use crate::{checked_op, errors::DecimalError};
+use serde::Serialize;
+use std::{fmt::Display, str::FromStr};
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub struct WideDecimal<const PRECISION: u32> {
+    n: i128,
+}
+
+impl<const PRECISION: u32> WideDecimal<PRECISION> {
+    pub const fn zero() -> Self {
+        Self { n: 0 }
+    }
+
+    pub fn format(&self, format: DecimalFormat) -> String {
+        format_fixed(self.n, PRECISION, format)
+    }
+
+    pub fn checked_sum<I>(iter: I) -> Result<Self, DecimalError>
+    where
+        I: IntoIterator<Item = Self>,
+    {
+        iter.into_iter()
+            .try_fold(Self::zero(), |acc, d| acc.checked_add(d))
+    }
+}
+
+impl<const PRECISION: u32> Serialize for WideDecimal<PRECISION> {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: serde::Serializer,
+    {
+        serializer.serialize_str(&self.format(DecimalFormat::Trimmed))
+    }
+}
+
+impl<const PRECISION: u32> FromStr for WideDecimal<PRECISION> {
+    type Err = DecimalError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        parse_fixed(s, PRECISION).map(|n| Self { n })
+    }
+}
+
+impl<const PRECISION: u32> Display for WideDecimal<PRECISION> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let format = if f.alternate() {
+            DecimalFormat::FullScale
+        } else {
+            DecimalFormat::Trimmed
+        };
+
+        f.write_str(&self.format(format))
+    }
+}
+
+impl<const PRECISION: u32> From<Decimal<PRECISION>> for WideDecimal<PRECISION> {
+    fn from(d: Decimal<PRECISION>) -> Self {
+        Self { n: d.n as i128 }
+    }
+}
+
+impl<const PRECISION: u32> TryFrom<WideDecimal<PRECISION>> for Decimal<PRECISION> {
+    type Error = DecimalError;
+
+    fn try_from(d: WideDecimal<PRECISION>) -> Result<Self, Self::Error> {
+        i64::try_from(d.n)
+            .map(|n| Decimal { n })
+            .map_err(|_| DecimalError::Overflow)
+    }
+}
+
+checked_op!(WideDecimal, checked_add);
+checked_op!(WideDecimal, checked_sub);
+
+#[cfg(test)]
+mod tests {
+    use super::WideDecimal;
+    use crate::{decimal::Decimal, errors::DecimalError};
+
+    #[test]
+    fn sum_past_decimal_range() {
+        let max = Decimal::<4>::try_from(WideDecimal::<4> {
+            n: i64::MAX as i128,
+        })
+        .unwrap();
+        let total = WideDecimal::checked_sum([max, max, max].map(WideDecimal::from)).unwrap();
+
+        assert_eq!(
+            total,
+            WideDecimal {
+                n: 3 * i64::MAX as i128
+            }
+        );
+        assert_eq!(total.to_string(), "2767011611056432.7421");
+        assert_eq!(Decimal::try_from(total), Err(DecimalError::Overflow));
+        assert_eq!(
+            Decimal::try_from(
+                total
+                    .checked_sub(WideDecimal::from(max))
+                    .and_then(|t| t.checked_sub(WideDecimal::from(max)))
+                    .unwrap()
+            ),
+            Ok(max)
+        );
+    }
+
+    #[test]
+    fn parse_wide() {
+        let d: WideDecimal<18> = "123456789.123456789012345678".parse().unwrap();
+
+        assert_eq!(format!("{:#}", d), "123456789.123456789012345678");
+    }
+}
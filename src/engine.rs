@@ -1,53 +1,466 @@
 use crate::{
-    client::{Client, ClientId},
-    errors::EngineError,
-    transaction::{Transaction, TransactionId, TransactionType},
+    asset::Asset,
+    client::{Client, ClientId, Reason},
+    decimal::{Amount, WideAmount},
+    errors::{DecimalError, EngineError, JournalError},
+    row::Row,
+    transaction::{Timestamp, Transaction, TransactionId, TransactionType},
 };
-use std::collections::{hash_map::Iter, HashMap};
+use std::{
+    collections::{hash_map::Iter, HashMap, HashSet, VecDeque},
+    error::Error,
+    io::{Read, Write},
+};
+
+pub use authorize::Authorization;
+pub use config::{
+    parse_dispute_rules, ChargebackPolicy, DisputePolicy, DisputeRules, EngineConfig, ResolvePolicy,
+};
+pub use deposit::NegativeBalancePolicy;
+pub use events::{BalanceChange, Event, EventSink};
+pub use fees::{Fee, FeeRefund, FeeSchedule, Tier};
+pub use handler::{Admin, Handlers, Reference, TransactionHandler};
+pub use journal::Journal;
+pub use limits::{LimitSchedule, LimitSetting, Limits, Window};
+pub use snapshot::SNAPSHOT_VERSION;
 
+mod authorize;
+mod capture;
 mod chargeback;
+mod config;
+mod credit;
 mod deposit;
 mod dispute;
+mod events;
+mod fees;
+mod freeze;
+mod handler;
+mod journal;
+mod limits;
 mod resolve;
+mod snapshot;
+#[cfg(test)]
+pub(crate) mod testing;
+mod transfer;
+mod unlock;
+mod void;
 mod withdrawal;
 
 pub struct Engine {
     clients: HashMap<ClientId, Client>,
     transactions: HashMap<TransactionId, Transaction>,
+    /// Ids of rejected rows of stored types, which can't be reused either
+    rejected: HashSet<TransactionId>,
+    lock_history: HashMap<ClientId, Vec<LockChange>>,
+    authorizations: HashMap<TransactionId, Authorization>,
+    /// Latest timestamp seen in the input
+    now: Option<Timestamp>,
+    fees: FeeSchedule,
+    config: EngineConfig,
+    limits: LimitSchedule,
+    movements: HashMap<(ClientId, Asset), VecDeque<limits::Movement>>,
+    handlers: Handlers,
+    sinks: Vec<Box<dyn EventSink + Send>>,
+    journal: Option<Journal>,
+    /// Set once the journal failed to record a row, no rows run after it
+    journal_failure: Option<JournalError>,
+}
+
+/// What a row executed by its handler changed, until it is committed or
+/// rolled back. `None` in `locked_before` marks accounts the row creates.
+struct Staged {
+    parties: Vec<ClientId>,
+    locked_before: Vec<Option<bool>>,
+    transaction_before: Option<Transaction>,
+    authorization_before: Option<Authorization>,
+}
+
+/// An account got locked or unlocked by transaction `tx`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LockChange {
+    pub tx: TransactionId,
+    pub locked: bool,
+    pub reason: Option<Reason>,
+}
+
+/// Sums of one asset over every account, wide so that they can't overflow
+pub struct TotalFunds {
+    pub available: WideAmount,
+    pub held: WideAmount,
+    pub total: WideAmount,
+}
+
+impl Default for Engine {
+    fn default() -> Self {
+        Self::new(EngineConfig::default())
+    }
 }
 
 impl Engine {
-    pub fn new() -> Self {
+    pub fn new(config: EngineConfig) -> Self {
         Self {
             clients: HashMap::new(),
             transactions: HashMap::new(),
+            rejected: HashSet::new(),
+            lock_history: HashMap::new(),
+            authorizations: HashMap::new(),
+            now: None,
+            fees: FeeSchedule::default(),
+            config,
+            limits: LimitSchedule::default(),
+            movements: HashMap::new(),
+            handlers: Handlers::default(),
+            sinks: Vec::new(),
+            journal: None,
+            journal_failure: None,
+        }
+    }
+
+    pub fn with_fees(self, fees: FeeSchedule) -> Self {
+        Self { fees, ..self }
+    }
+
+    pub fn with_limits(self, limits: LimitSchedule) -> Self {
+        Self { limits, ..self }
+    }
+
+    /// Appends every row executed from now on to `journal`
+    pub fn with_journal(self, journal: Journal) -> Self {
+        Self {
+            journal: Some(journal),
+            ..self
         }
     }
 
-    pub fn execute(&mut self, transaction: &Transaction) -> Result<(), EngineError> {
-        let result = match transaction.kind {
-            TransactionType::DEPOSIT => deposit::execute(self, transaction),
-            TransactionType::WITHDRAWAL => withdrawal::execute(self, transaction),
-            TransactionType::DISPUTE => dispute::execute(self, transaction),
-            TransactionType::RESOLVE => resolve::execute(self, transaction),
-            TransactionType::CHARGEBACK => chargeback::execute(self, transaction),
+    /// Executes the rows of a journal, up to the first one with id `until`.
+    /// Nothing is appended to the engine's own journal meanwhile.
+    pub fn replay<R: Read>(
+        &mut self,
+        reader: R,
+        until: Option<TransactionId>,
+    ) -> Result<(), Box<dyn Error>> {
+        let journal = self.journal.take();
+        let result = journal::replay(self, reader, until);
+        self.journal = journal;
+
+        result
+    }
+
+    /// Saves the accounts, stored transactions and everything else rows may
+    /// change; the configuration isn't part of it
+    pub fn snapshot<W: Write>(&self, writer: W) -> Result<(), Box<dyn Error>> {
+        snapshot::write(self, writer)
+    }
+
+    /// Replaces the state with a snapshot. Per-client limits from the snapshot
+    /// override the ones the engine was created with.
+    pub fn restore<R: Read>(&mut self, reader: R) -> Result<(), Box<dyn Error>> {
+        snapshot::read(self, reader)
+    }
+
+    pub fn with_handler<H>(mut self, kind: TransactionType, handler: H) -> Self
+    where
+        H: TransactionHandler + Send + Sync + 'static,
+    {
+        self.register_handler(kind, handler);
+        self
+    }
+
+    /// Adds a custom transaction type or replaces how a known one is handled
+    pub fn register_handler<H>(&mut self, kind: TransactionType, handler: H)
+    where
+        H: TransactionHandler + Send + Sync + 'static,
+    {
+        self.handlers.register(kind, handler);
+    }
+
+    /// Whether rows of `kind` can be executed
+    pub fn has_handler(&self, kind: TransactionType) -> bool {
+        self.handlers.contains(kind)
+    }
+
+    /// Publishes the events of every row executed from now on to `sink`
+    pub fn subscribe<S: EventSink + Send + 'static>(&mut self, sink: S) {
+        self.sinks.push(Box::new(sink));
+    }
+
+    pub fn config(&self) -> &EngineConfig {
+        &self.config
+    }
+
+    /// Reads `client,credit_limit` rows, creating the accounts if needed
+    pub fn load_credit_lines<R: Read>(&mut self, reader: R) -> Result<(), Box<dyn Error>> {
+        credit::load(self, reader)
+    }
+
+    pub fn set_credit_limit(&mut self, id: ClientId, limit: Amount) -> Result<(), EngineError> {
+        if limit < Amount::zero() {
+            return Err(EngineError::NegativeAmount(limit));
+        }
+
+        self.clients
+            .entry(id)
+            .or_insert(Client::new(id))
+            .set_credit_limit(limit)
+    }
+
+    /// Executes a row from the input, rows of admin types are rejected
+    pub fn execute(&mut self, row: &Row) -> Result<(), EngineError> {
+        self.run(row, false)
+    }
+
+    /// Executes a row from the admin source, which may be of any type
+    pub fn execute_admin(&mut self, row: &Row) -> Result<(), EngineError> {
+        self.run(row, true)
+    }
+
+    /// Why the journal stopped recording rows, if it did. The row it failed
+    /// on was rolled back and refused like every later one.
+    pub fn journal_failure(&self) -> Option<&JournalError> {
+        self.journal_failure.as_ref()
+    }
+
+    fn run(&mut self, row: &Row, admin: bool) -> Result<(), EngineError> {
+        if self.journal_failure.is_some() {
+            return Err(EngineError::JournalFailed);
+        }
+
+        // An executed row is only committed once the journal recorded it
+        let result = match self.apply(row, admin) {
+            Ok(Some(staged)) => match self.journal(row, admin, Ok(())) {
+                Ok(()) => {
+                    self.commit(row, staged);
+                    Ok(())
+                }
+                Err(error) => {
+                    self.rollback(row, staged);
+                    Err(error)
+                }
+            },
+            Ok(None) => self.journal(row, admin, Ok(())),
+            Err(error) => self.journal(row, admin, Err(error)),
         };
 
+        if let Err(error) = result {
+            self.publish(&Event::TransactionRejected {
+                tx: row.tx,
+                client: row.client,
+                kind: row.kind(),
+                error,
+            });
+        }
         result
     }
 
-    pub fn get_client<'a>(&'a self, id: ClientId) -> Option<&'a Client> {
+    /// Records the outcome of a row, once that fails the row fails with
+    /// `JournalFailed` and no further rows run
+    fn journal(
+        &mut self,
+        row: &Row,
+        admin: bool,
+        result: Result<(), EngineError>,
+    ) -> Result<(), EngineError> {
+        if let Some(journal) = &mut self.journal {
+            if let Err(cause) = journal.append(row, admin, &result) {
+                self.journal_failure = Some(JournalError {
+                    tx: row.tx,
+                    cause: cause.to_string(),
+                });
+                return Err(EngineError::JournalFailed);
+            }
+        }
+        result
+    }
+
+    /// Executes the row, leaving what it changed to be committed or rolled
+    /// back. Rows that are no-ops (replays) return `None`.
+    fn apply(&mut self, row: &Row, admin: bool) -> Result<Option<Staged>, EngineError> {
+        if row.timestamp > self.now {
+            self.now = row.timestamp;
+            authorize::expire(self)?;
+        }
+
+        if let Some(house) = self.fees.house() {
+            if row.client == house || row.destination() == Some(house) {
+                return Err(EngineError::ReservedClient(house));
+            }
+        }
+
+        let handler = self.handlers.get(row.kind())?;
+        if handler.is_admin() && !admin {
+            return Err(EngineError::AdminOnly(row.kind()));
+        }
+
+        // Replaying a transaction is a no-op, reusing its id is an error. The
+        // id of a rejected row stays taken.
+        if handler.is_stored() {
+            if let Some(existing) = self.transactions.get(&row.tx) {
+                return if existing.same_payload(row) {
+                    Ok(None)
+                } else {
+                    Err(EngineError::DuplicateTransaction(row.tx))
+                };
+            }
+            if self.rejected.contains(&row.tx) {
+                return Err(EngineError::DuplicateTransaction(row.tx));
+            }
+        }
+
+        // Everything a transaction may touch is restored if it fails halfway:
+        // the accounts through their undo logs, the referenced transaction and
+        // authorization from a copy. `None` marks accounts the row creates.
+        let parties = self.parties(row);
+        let locked_before: Vec<Option<bool>> = parties
+            .iter()
+            .map(|id| {
+                self.clients.get_mut(id).map(|c| {
+                    c.begin();
+                    c.is_locked()
+                })
+            })
+            .collect();
+        let staged = Staged {
+            parties,
+            locked_before,
+            transaction_before: self.transactions.get(&row.tx).copied(),
+            authorization_before: self.authorizations.get(&row.tx).copied(),
+        };
+
+        if let Err(error) = handler.execute(self, row) {
+            self.rollback(row, staged);
+            if handler.is_stored() {
+                self.rejected.insert(row.tx);
+            }
+            return Err(error);
+        }
+
+        Ok(Some(staged))
+    }
+
+    fn rollback(&mut self, row: &Row, staged: Staged) {
+        for (id, locked) in staged.parties.iter().zip(staged.locked_before) {
+            match locked {
+                Some(_) => self.clients.get_mut(id).map(Client::rollback),
+                None => self.clients.remove(id).map(|_| ()),
+            };
+        }
+        match staged.transaction_before {
+            Some(t) => self.transactions.insert(row.tx, t),
+            None => self.transactions.remove(&row.tx),
+        };
+        match staged.authorization_before {
+            Some(a) => self.authorizations.insert(row.tx, a),
+            None => self.authorizations.remove(&row.tx),
+        };
+    }
+
+    fn commit(&mut self, row: &Row, staged: Staged) {
+        limits::record(self, row, staged.authorization_before);
+
+        let mut events = Vec::new();
+        for (id, locked_before) in staged.parties.into_iter().zip(staged.locked_before) {
+            if let Some(client) = self.clients.get_mut(&id) {
+                if !self.sinks.is_empty() {
+                    events.extend(events::balance_changes(row.tx, client));
+                }
+                client.commit();
+            }
+
+            let locked = self.is_locked(id);
+            if locked != locked_before.unwrap_or_default() {
+                self.lock_history.entry(id).or_default().push(LockChange {
+                    tx: row.tx,
+                    locked,
+                    reason: row.reason,
+                });
+                events.push(if locked {
+                    Event::AccountLocked {
+                        tx: row.tx,
+                        client: id,
+                    }
+                } else {
+                    Event::AccountUnlocked {
+                        tx: row.tx,
+                        client: id,
+                    }
+                });
+            }
+        }
+        for event in &events {
+            self.publish(event);
+        }
+    }
+
+    fn publish(&mut self, event: &Event) {
+        for sink in self.sinks.iter_mut() {
+            sink.publish(event);
+        }
+    }
+
+    /// Clients whose account a transaction might change
+    fn parties(&self, row: &Row) -> Vec<ClientId> {
+        let referenced = self.transactions.get(&row.tx);
+        let mut parties: Vec<ClientId> = [
+            Some(row.client),
+            self.fees.house(),
+            row.destination(),
+            referenced.map(|t| t.client),
+            referenced.and_then(|t| t.destination),
+        ]
+        .into_iter()
+        .flatten()
+        .collect();
+
+        parties.sort();
+        parties.dedup();
+        parties
+    }
+
+    fn is_locked(&self, id: ClientId) -> bool {
+        self.clients.get(&id).is_some_and(Client::is_locked)
+    }
+
+    pub fn lock_history(&self, id: ClientId) -> &[LockChange] {
+        self.lock_history.get(&id).map_or(&[], Vec::as_slice)
+    }
+
+    pub fn get_client(&self, id: ClientId) -> Option<&Client> {
         self.clients.get(&id)
     }
 
-    pub fn get_mut_transaction_client_pair<'a>(
-        &'a mut self,
+    pub fn get_mut_client(&mut self, id: ClientId) -> Result<&mut Client, EngineError> {
+        self.clients
+            .get_mut(&id)
+            .ok_or(EngineError::ClientNotFound(id))
+    }
+
+    /// Creates the account on first use, like a deposit does
+    pub fn get_or_insert_client(&mut self, id: ClientId) -> &mut Client {
+        self.clients.entry(id).or_insert(Client::new(id))
+    }
+
+    pub fn get_transaction(&self, tx: TransactionId) -> Option<&Transaction> {
+        self.transactions.get(&tx)
+    }
+
+    /// Stores a transaction so that later rows can reference it
+    pub fn insert_transaction(&mut self, transaction: Transaction) {
+        self.transactions.insert(transaction.tx, transaction);
+    }
+
+    /// Looks up a transaction referenced by `client`, who has to own it
+    pub fn get_mut_transaction_client_pair(
+        &mut self,
         tx: TransactionId,
-    ) -> Result<(&'a mut Transaction, &'a mut Client), EngineError> {
+        client: ClientId,
+    ) -> Result<(&mut Transaction, &mut Client), EngineError> {
         let t = self
             .transactions
             .get_mut(&tx)
             .ok_or(EngineError::TransactionNotFound(tx))?;
+        if t.client != client {
+            return Err(EngineError::TransactionNotOwned(tx, client));
+        }
 
         let c = self
             .clients
@@ -57,6 +470,78 @@ impl Engine {
         Ok((t, c))
     }
 
+    /// Looks up an open authorization of `client`
+    pub fn get_mut_authorization_client_pair(
+        &mut self,
+        tx: TransactionId,
+        client: ClientId,
+    ) -> Result<(&mut Authorization, &mut Client), EngineError> {
+        let a = self
+            .authorizations
+            .get_mut(&tx)
+            .ok_or(EngineError::AuthorizationClosed(tx))?;
+        if a.client != client {
+            return Err(EngineError::TransactionNotOwned(tx, client));
+        }
+
+        let c = self
+            .clients
+            .get_mut(&a.client)
+            .ok_or(EngineError::ClientNotFound(a.client))?;
+
+        Ok((a, c))
+    }
+
+    /// Open authorizations of a client, along with the funds each one holds
+    pub fn authorizations(
+        &self,
+        client: ClientId,
+    ) -> impl Iterator<Item = (TransactionId, &Authorization)> {
+        self.authorizations
+            .iter()
+            .filter(move |(_, a)| a.client == client)
+            .map(|(tx, a)| (*tx, a))
+    }
+
+    /// Returns the transfer along with its source and destination clients,
+    /// the source has to be `client`
+    pub fn get_mut_transfer_parties(
+        &mut self,
+        tx: TransactionId,
+        client: ClientId,
+    ) -> Result<(&mut Transaction, &mut Client, &mut Client), EngineError> {
+        let t = self
+            .transactions
+            .get_mut(&tx)
+            .ok_or(EngineError::TransactionNotFound(tx))?;
+        if t.client != client {
+            return Err(EngineError::TransactionNotOwned(tx, client));
+        }
+
+        let destination = t
+            .destination
+            .filter(|d| *d != t.client)
+            .ok_or(EngineError::InvalidDestination(tx))?;
+
+        let [source, target] = self.clients.get_disjoint_mut([&t.client, &destination]);
+        let source = source.ok_or(EngineError::ClientNotFound(t.client))?;
+        let target = target.ok_or(EngineError::ClientNotFound(destination))?;
+
+        Ok((t, source, target))
+    }
+
+    pub fn total_funds(&self, asset: Asset) -> Result<TotalFunds, DecimalError> {
+        let funds = self.clients.values().map(|c| c.get_funds(asset));
+        let available = WideAmount::checked_sum(funds.clone().map(|f| f.available.into()))?;
+        let held = WideAmount::checked_sum(funds.map(|f| f.held.into()))?;
+
+        Ok(TotalFunds {
+            available,
+            held,
+            total: available.checked_add(held)?,
+        })
+    }
+
     pub fn iter_clients<'a>(&'a self) -> Iter<'a, ClientId, Client> {
         self.clients.iter()
     }
@@ -0,0 +1,235 @@
+use super::{events, Engine, TransactionHandler};
+use crate::asset::Asset;
+use crate::client::ClientId;
+use crate::decimal::Amount;
+use crate::errors::EngineError;
+use crate::row::{Action, Row};
+use crate::transaction::{Timestamp, Transaction, TransactionId};
+
+/// Funds reserved by an authorization that were not captured yet
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Authorization {
+    pub client: ClientId,
+    pub asset: Asset,
+    pub amount: Amount,
+    pub expires: Option<Timestamp>,
+}
+
+impl Authorization {
+    pub fn is_expired(&self, now: Option<Timestamp>) -> bool {
+        matches!((self.expires, now), (Some(expires), Some(now)) if expires <= now)
+    }
+}
+
+pub fn execute(e: &mut Engine, row: &Row) -> Result<(), EngineError> {
+    let Action::Authorize {
+        amount,
+        asset,
+        expires,
+    } = row.action
+    else {
+        return Err(EngineError::InvalidTransactionType);
+    };
+    let authorization = Authorization {
+        client: row.client,
+        asset,
+        amount,
+        expires,
+    };
+    if authorization.is_expired(e.now) {
+        return Err(EngineError::AuthorizationClosed(row.tx));
+    }
+
+    e.clients
+        .get_mut(&row.client)
+        .ok_or(EngineError::ClientNotFound(row.client))?
+        .active()?
+        .reserve_funds(asset, amount)?;
+
+    if let Some(transaction) = Transaction::from_row(row) {
+        e.transactions.insert(row.tx, transaction);
+    }
+    e.authorizations.insert(row.tx, authorization);
+
+    Ok(())
+}
+
+/// Authorizations are stored but can't be disputed, only captured or voided
+pub struct Handler;
+
+impl TransactionHandler for Handler {
+    fn is_stored(&self) -> bool {
+        true
+    }
+
+    fn execute(&self, e: &mut Engine, row: &Row) -> Result<(), EngineError> {
+        execute(e, row)
+    }
+}
+
+/// Releases whatever is still held by the authorization and closes it
+pub fn close(e: &mut Engine, tx: TransactionId, client: ClientId) -> Result<(), EngineError> {
+    let (authorization, c) = e.get_mut_authorization_client_pair(tx, client)?;
+
+    c.release_funds(authorization.asset, authorization.amount)?;
+    e.authorizations.remove(&tx);
+
+    Ok(())
+}
+
+/// Closes the expired authorizations, whatever state their accounts are in,
+/// publishing the releases under the id of each authorization
+pub fn expire(e: &mut Engine) -> Result<(), EngineError> {
+    let expired: Vec<(TransactionId, ClientId)> = e
+        .authorizations
+        .iter()
+        .filter(|(_, a)| a.is_expired(e.now))
+        .map(|(tx, a)| (*tx, a.client))
+        .collect();
+
+    for (tx, client) in expired {
+        if let Some(c) = e.clients.get_mut(&client) {
+            c.begin();
+        }
+        let result = close(e, tx, client);
+
+        let Some(c) = e.clients.get_mut(&client) else {
+            return result;
+        };
+        if result.is_err() {
+            c.rollback();
+            return result;
+        }
+        let released = events::balance_changes(tx, c);
+        c.commit();
+        for event in &released {
+            e.publish(event);
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        asset::Asset,
+        decimal::Decimal,
+        engine::{testing::row, Engine},
+        errors::EngineError,
+        row::Row,
+        transaction::TransactionType,
+    };
+
+    fn setup() -> Engine {
+        let mut e = Engine::default();
+        e.execute(&row(TransactionType::DEPOSIT, 1, 1, 100))
+            .unwrap();
+        e
+    }
+
+    #[test]
+    fn authorize_capture_void() {
+        let mut e = setup();
+
+        assert!(matches!(
+            e.execute(&row(TransactionType::AUTHORIZE, 1, 3, 101)),
+            Err(EngineError::InsufficientFunds(..))
+        ));
+        e.execute(&row(TransactionType::AUTHORIZE, 1, 2, 60))
+            .unwrap();
+        e.execute(&row(TransactionType::CAPTURE, 1, 2, 25)).unwrap();
+
+        assert_eq!(
+            e.authorizations(1).next().unwrap().1.amount,
+            Decimal::from(35)
+        );
+
+        e.execute(&row(TransactionType::VOID, 1, 2, 0)).unwrap();
+
+        let funds = e.get_client(1).unwrap().get_funds(Asset::default());
+        assert_eq!(funds.available, Decimal::from(75));
+        assert_eq!(funds.held, Decimal::zero());
+        assert!(matches!(
+            e.execute(&row(TransactionType::CAPTURE, 1, 2, 0)),
+            Err(EngineError::AuthorizationClosed(2))
+        ));
+    }
+
+    #[test]
+    fn expired_authorization_is_released() {
+        let mut e = setup();
+
+        e.execute(
+            &row(TransactionType::AUTHORIZE, 1, 2, 60)
+                .with_timestamp(10)
+                .with_expiry(20),
+        )
+        .unwrap();
+        e.execute(&row(TransactionType::AUTHORIZE, 1, 3, 10).with_timestamp(15))
+            .unwrap();
+
+        assert_eq!(
+            e.get_client(1).unwrap().get_funds(Asset::default()).held,
+            Decimal::from(70)
+        );
+
+        e.execute(&row(TransactionType::DEPOSIT, 1, 4, 1).with_timestamp(20))
+            .unwrap();
+
+        assert_eq!(
+            e.get_client(1).unwrap().get_funds(Asset::default()).held,
+            Decimal::from(10)
+        );
+        assert!(matches!(
+            e.execute(&row(TransactionType::CAPTURE, 1, 2, 0)),
+            Err(EngineError::AuthorizationClosed(2))
+        ));
+    }
+
+    #[test]
+    fn failed_capture_keeps_authorization() {
+        let mut e = setup();
+
+        e.execute(&row(TransactionType::AUTHORIZE, 1, 2, 60))
+            .unwrap();
+        e.register_handler(TransactionType::CAPTURE, |e: &mut Engine, t: &Row| {
+            crate::engine::capture::execute(e, t)?;
+            Err(EngineError::InvalidTransactionType)
+        });
+
+        assert!(e.execute(&row(TransactionType::CAPTURE, 1, 2, 0)).is_err());
+        assert_eq!(
+            e.authorizations(1).next().unwrap().1.amount,
+            Decimal::from(60)
+        );
+        assert_eq!(
+            e.get_client(1).unwrap().get_funds(Asset::default()).held,
+            Decimal::from(60)
+        );
+    }
+
+    #[test]
+    fn locked_account_authorization_expires() {
+        let mut e = setup();
+
+        e.execute(
+            &row(TransactionType::AUTHORIZE, 1, 2, 60)
+                .with_timestamp(10)
+                .with_expiry(20),
+        )
+        .unwrap();
+        e.execute(&row(TransactionType::DISPUTE, 1, 1, 40)).unwrap();
+        e.execute(&row(TransactionType::CHARGEBACK, 1, 1, 0))
+            .unwrap();
+        assert!(e.get_client(1).unwrap().is_locked());
+
+        e.execute(&row(TransactionType::DEPOSIT, 2, 3, 1).with_timestamp(20))
+            .unwrap();
+
+        let funds = e.get_client(1).unwrap().get_funds(Asset::default());
+        assert_eq!(funds.available, Decimal::from(60));
+        assert_eq!(funds.held, Decimal::zero());
+        assert_eq!(e.authorizations(1).count(), 0);
+    }
+}
@@ -0,0 +1,31 @@
+use super::{limits, Engine};
+use crate::decimal::Amount;
+use crate::errors::EngineError;
+use crate::row::{Action, Row};
+
+/// Takes part (or with no amount, all) of the authorized funds. Whatever is
+/// left stays reserved until captured, voided or expired. The captured amount
+/// is what leaves the account, so it is what the withdrawal limits look at.
+pub fn execute(e: &mut Engine, row: &Row) -> Result<(), EngineError> {
+    let Action::Capture { amount } = row.action else {
+        return Err(EngineError::InvalidTransactionType);
+    };
+    let (authorization, _) = e.get_mut_authorization_client_pair(row.tx, row.client)?;
+    let (open, asset) = (authorization.amount, authorization.asset);
+
+    let amount = amount.unwrap_or(open);
+    if amount > open {
+        return Err(EngineError::CaptureAmountTooLarge(row.tx, open));
+    }
+    limits::check_withdrawal(e, row.client, asset, amount)?;
+
+    let (authorization, client) = e.get_mut_authorization_client_pair(row.tx, row.client)?;
+    client.active()?.capture_funds(asset, amount)?;
+    authorization.amount = open.checked_sub(amount)?;
+
+    if authorization.amount == Amount::zero() {
+        e.authorizations.remove(&row.tx);
+    }
+
+    Ok(())
+}
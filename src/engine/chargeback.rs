@@ -1,12 +1,14 @@
-use super::{deposit, withdrawal, Engine};
-use crate::{errors::EngineError, transaction::Transaction};
+use super::{fees, Engine, Reference};
+use crate::{errors::EngineError, row::Row};
 
-pub fn execute(e: &mut Engine, transaction: &Transaction) -> Result<(), EngineError> {
-    let (target, client) = e.get_mut_transaction_client_pair(transaction.tx)?;
+/// Acts on the referenced transaction through the handler of its type
+pub fn execute(e: &mut Engine, row: &Row) -> Result<(), EngineError> {
+    let reference = Reference::from_row(row)?;
+    let kind = e
+        .get_transaction(reference.tx)
+        .ok_or(EngineError::TransactionNotFound(reference.tx))?
+        .kind;
 
-    match target.kind {
-        crate::transaction::TransactionType::DEPOSIT => deposit::revert(client, target),
-        crate::transaction::TransactionType::WITHDRAWAL => withdrawal::revert(client, target),
-        _ => Err(EngineError::InvalidTransactionType),
-    }
+    e.handlers.get(kind)?.revert(e, &reference)?;
+    fees::refund(e, &reference)
 }
@@ -0,0 +1,279 @@
+//! Engine-wide settings fixed when the engine is created. Disputes are
+//! configured per type of the disputed transaction: what a dispute does,
+//! whether it may be resolved (which undoes it) and whether a chargeback
+//! locks the account.
+use super::NegativeBalancePolicy;
+use crate::{client::Client, errors::EngineError, transaction::TransactionType};
+use std::{collections::HashMap, str::FromStr};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DisputePolicy {
+    /// The disputed funds are held until resolved or charged back
+    Hold,
+    /// Nothing moves until a chargeback reverses the transaction
+    Record,
+    /// The transaction can't be disputed
+    Reject,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ResolvePolicy {
+    /// The dispute is undone, the transaction stands as it was
+    Reinstate,
+    /// A dispute can only end with a chargeback
+    Reject,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ChargebackPolicy {
+    Lock,
+    KeepActive,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DisputeRules {
+    pub dispute: DisputePolicy,
+    pub resolve: ResolvePolicy,
+    pub chargeback: ChargebackPolicy,
+}
+
+#[derive(Debug, Clone)]
+pub struct EngineConfig {
+    pub negative_balance: NegativeBalancePolicy,
+    disputes: HashMap<TransactionType, DisputeRules>,
+}
+
+/// Deposits and transfers hold the disputed funds, withdrawals are only
+/// credited back on chargeback. Every dispute may be resolved and every
+/// chargeback locks the account.
+impl Default for EngineConfig {
+    fn default() -> Self {
+        let rules = |dispute| DisputeRules {
+            dispute,
+            resolve: ResolvePolicy::Reinstate,
+            chargeback: ChargebackPolicy::Lock,
+        };
+
+        Self {
+            negative_balance: NegativeBalancePolicy::default(),
+            disputes: HashMap::from([
+                (TransactionType::DEPOSIT, rules(DisputePolicy::Hold)),
+                (TransactionType::WITHDRAWAL, rules(DisputePolicy::Record)),
+                (TransactionType::TRANSFER, rules(DisputePolicy::Hold)),
+            ]),
+        }
+    }
+}
+
+impl EngineConfig {
+    pub fn with_negative_balance_policy(self, negative_balance: NegativeBalancePolicy) -> Self {
+        Self {
+            negative_balance,
+            ..self
+        }
+    }
+
+    pub fn with_dispute_rules(mut self, kind: TransactionType, rules: DisputeRules) -> Self {
+        self.set_dispute_rules(kind, rules);
+        self
+    }
+
+    pub fn set_dispute_rules(&mut self, kind: TransactionType, rules: DisputeRules) {
+        self.disputes.insert(kind, rules);
+    }
+
+    /// Transactions without rules can't be disputed
+    pub fn dispute_rules(&self, kind: TransactionType) -> Result<DisputeRules, EngineError> {
+        self.disputes
+            .get(&kind)
+            .copied()
+            .ok_or(EngineError::InvalidTransactionType)
+    }
+
+    /// Every setting in the command line syntax, ordered by type name, e.g.
+    /// `negative-balance=allow deposit=hold/reinstate/lock`. Equal for equal
+    /// configurations, so snapshots can tell which one they were taken under.
+    pub fn fingerprint(&self) -> String {
+        let mut disputes: Vec<String> = self
+            .disputes
+            .iter()
+            .map(|(kind, rules)| {
+                format!(
+                    "{}={}/{}/{}",
+                    kind.name(),
+                    rules.dispute.name(),
+                    rules.resolve.name(),
+                    rules.chargeback.name()
+                )
+            })
+            .collect();
+        disputes.sort();
+
+        let mut settings = vec![format!("negative-balance={}", self.negative_balance.name())];
+        settings.extend(disputes);
+        settings.join(" ")
+    }
+}
+
+impl DisputePolicy {
+    pub fn name(&self) -> &str {
+        match self {
+            DisputePolicy::Hold => "hold",
+            DisputePolicy::Record => "record",
+            DisputePolicy::Reject => "reject",
+        }
+    }
+
+    pub fn check(&self, kind: TransactionType) -> Result<(), EngineError> {
+        match self {
+            DisputePolicy::Reject => Err(EngineError::DisputeRejected(kind)),
+            _ => Ok(()),
+        }
+    }
+}
+
+impl ResolvePolicy {
+    pub fn name(&self) -> &str {
+        match self {
+            ResolvePolicy::Reinstate => "reinstate",
+            ResolvePolicy::Reject => "reject",
+        }
+    }
+
+    pub fn check(&self, kind: TransactionType) -> Result<(), EngineError> {
+        match self {
+            ResolvePolicy::Reject => Err(EngineError::ResolveRejected(kind)),
+            ResolvePolicy::Reinstate => Ok(()),
+        }
+    }
+}
+
+impl ChargebackPolicy {
+    pub fn name(&self) -> &str {
+        match self {
+            ChargebackPolicy::Lock => "lock",
+            ChargebackPolicy::KeepActive => "keep-active",
+        }
+    }
+
+    /// Called once the funds of a chargeback have moved. An account locked
+    /// by an earlier (partial) chargeback stays locked.
+    pub fn apply(&self, client: &mut Client) -> Result<(), EngineError> {
+        match self {
+            ChargebackPolicy::Lock if !client.is_locked() => client.lock(),
+            _ => Ok(()),
+        }
+    }
+}
+
+/// Parses `<type>=<dispute>[/<chargeback>]` or
+/// `<type>=<dispute>/<resolve>/<chargeback>`, e.g. `withdrawal=hold/keep-active`
+/// or `deposit=hold/reject/lock`
+pub fn parse_dispute_rules(s: &str) -> Result<(TransactionType, DisputeRules), String> {
+    let invalid = || format!("Invalid dispute rules {}.", s);
+    let (kind, rules) = s.split_once('=').ok_or_else(invalid)?;
+    let (dispute, resolve, chargeback) = match rules.split('/').collect::<Vec<_>>()[..] {
+        [dispute] => (dispute, "reinstate", "lock"),
+        [dispute, chargeback] => (dispute, "reinstate", chargeback),
+        [dispute, resolve, chargeback] => (dispute, resolve, chargeback),
+        _ => return Err(invalid()),
+    };
+
+    let kind = match kind {
+        "deposit" => TransactionType::DEPOSIT,
+        "withdrawal" => TransactionType::WITHDRAWAL,
+        "transfer" => TransactionType::TRANSFER,
+        _ => return Err(invalid()),
+    };
+
+    Ok((
+        kind,
+        DisputeRules {
+            dispute: dispute.parse()?,
+            resolve: resolve.parse()?,
+            chargeback: chargeback.parse()?,
+        },
+    ))
+}
+
+impl FromStr for DisputePolicy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "hold" => Ok(DisputePolicy::Hold),
+            "record" => Ok(DisputePolicy::Record),
+            "reject" => Ok(DisputePolicy::Reject),
+            _ => Err(format!(
+                "Unknown dispute policy {}, expected hold, record or reject.",
+                s
+            )),
+        }
+    }
+}
+
+impl FromStr for ResolvePolicy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "reinstate" => Ok(ResolvePolicy::Reinstate),
+            "reject" => Ok(ResolvePolicy::Reject),
+            _ => Err(format!(
+                "Unknown resolve policy {}, expected reinstate or reject.",
+                s
+            )),
+        }
+    }
+}
+
+impl FromStr for ChargebackPolicy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "lock" => Ok(ChargebackPolicy::Lock),
+            "keep-active" => Ok(ChargebackPolicy::KeepActive),
+            _ => Err(format!(
+                "Unknown chargeback policy {}, expected lock or keep-active.",
+                s
+            )),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        parse_dispute_rules, ChargebackPolicy, DisputePolicy, DisputeRules, ResolvePolicy,
+    };
+    use crate::transaction::TransactionType;
+
+    #[test]
+    fn parse_rules() {
+        let rules = |s| parse_dispute_rules(s).map(|(_, rules)| rules);
+
+        assert_eq!(
+            parse_dispute_rules("withdrawal=hold"),
+            Ok((
+                TransactionType::WITHDRAWAL,
+                DisputeRules {
+                    dispute: DisputePolicy::Hold,
+                    resolve: ResolvePolicy::Reinstate,
+                    chargeback: ChargebackPolicy::Lock,
+                }
+            ))
+        );
+        assert_eq!(
+            rules("deposit=record/keep-active").map(|r| (r.resolve, r.chargeback)),
+            Ok((ResolvePolicy::Reinstate, ChargebackPolicy::KeepActive))
+        );
+        assert_eq!(
+            rules("deposit=hold/reject/lock").map(|r| (r.resolve, r.chargeback)),
+            Ok((ResolvePolicy::Reject, ChargebackPolicy::Lock))
+        );
+        assert!(rules("deposit=hold/reject").is_err());
+        assert!(rules("deposit=hold/reject/lock/lock").is_err());
+        assert!(rules("authorize=hold").is_err());
+    }
+}
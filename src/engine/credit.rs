@@ -0,0 +1,124 @@
+/**
+ * Credit lines let the available funds of a client go below zero, down to
+ * the negative credit limit. They are set by admin rows or loaded from a
+ * file, either way creating the account if needed.
+ */
+use super::Engine;
+use crate::{
+    client::ClientId,
+    decimal::Amount,
+    errors::EngineError,
+    row::{Action, Row},
+};
+use serde::Deserialize;
+use std::{error::Error, io::Read};
+
+#[derive(Deserialize)]
+struct CreditRow {
+    client: ClientId,
+    credit_limit: Amount,
+}
+
+/// Admin row, the amount is the new credit limit
+pub fn execute(e: &mut Engine, row: &Row) -> Result<(), EngineError> {
+    let Action::Credit { amount } = row.action else {
+        return Err(EngineError::InvalidTransactionType);
+    };
+    e.set_credit_limit(row.client, amount)
+}
+
+/// Reads `client,credit_limit` rows
+pub fn load<R: Read>(e: &mut Engine, reader: R) -> Result<(), Box<dyn Error>> {
+    for row in csv::Reader::from_reader(reader).deserialize() {
+        let row: CreditRow = row?;
+        e.set_credit_limit(row.client, row.credit_limit)?;
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        decimal::Decimal,
+        engine::{
+            testing::{funds, row},
+            Engine,
+        },
+        errors::EngineError,
+        transaction::TransactionType,
+    };
+
+    #[test]
+    fn overdraft_within_credit_line() {
+        let mut e = Engine::default();
+        e.load_credit_lines("client,credit_limit\n1,50\n".as_bytes())
+            .unwrap();
+
+        e.execute(&row(TransactionType::DEPOSIT, 1, 1, 10)).unwrap();
+        assert!(matches!(
+            e.execute(&row(TransactionType::WITHDRAWAL, 1, 2, 61)),
+            Err(EngineError::InsufficientFunds(1, _, _))
+        ));
+        e.execute(&row(TransactionType::WITHDRAWAL, 1, 3, 60))
+            .unwrap();
+
+        let funds = funds(&e, 1);
+        assert_eq!(funds.available, Decimal::from(-50));
+        assert_eq!(funds.overdraft(), Ok(Decimal::from(50)));
+
+        // Lowering the line keeps the overdraft but blocks further debits
+        e.execute_admin(&row(TransactionType::CREDIT, 1, 4, 0))
+            .unwrap();
+        assert!(e
+            .execute(&row(TransactionType::WITHDRAWAL, 1, 5, 1))
+            .is_err());
+        e.execute(&row(TransactionType::DEPOSIT, 1, 6, 60)).unwrap();
+        e.execute(&row(TransactionType::WITHDRAWAL, 1, 7, 10))
+            .unwrap();
+    }
+
+    #[test]
+    fn negative_credit_limit() {
+        let mut e = Engine::default();
+
+        assert!(matches!(
+            e.execute_admin(&row(TransactionType::CREDIT, 1, 1, -1)),
+            Err(EngineError::NegativeAmount(_))
+        ));
+        assert!(e.get_client(1).is_none());
+
+        // An empty limit is malformed rather than zero
+        assert!(e
+            .load_credit_lines("client,credit_limit\n1,\n".as_bytes())
+            .is_err());
+        assert!(e.get_client(1).is_none());
+    }
+
+    #[test]
+    fn admin_rows_only_from_admin_source() {
+        let mut e = Engine::default();
+        e.execute(&row(TransactionType::DEPOSIT, 1, 1, 10)).unwrap();
+
+        for kind in [
+            TransactionType::CREDIT,
+            TransactionType::LIMIT,
+            TransactionType::UNLOCK,
+            TransactionType::FREEZE,
+            TransactionType::UNFREEZE,
+        ] {
+            assert_eq!(
+                e.execute(&row(kind, 1, 2, 100)),
+                Err(EngineError::AdminOnly(kind))
+            );
+        }
+        assert!(e
+            .execute(&row(TransactionType::WITHDRAWAL, 1, 3, 11))
+            .is_err());
+
+        e.execute_admin(&row(TransactionType::CREDIT, 1, 4, 100))
+            .unwrap();
+        e.execute(&row(TransactionType::WITHDRAWAL, 1, 5, 11))
+            .unwrap();
+    }
+}
@@ -1,65 +1,558 @@
-use super::Engine;
+use super::{fees, limits, DisputePolicy, DisputeRules, Engine, Reference, TransactionHandler};
 use crate::client::Client;
+use crate::decimal::Amount;
 use crate::errors::EngineError;
-use crate::transaction::{Transaction, TransactionDisputeStatus};
+use crate::row::{Action, Row};
+use crate::transaction::{Transaction, TransactionType};
+use std::str::FromStr;
+
+pub fn execute(e: &mut Engine, row: &Row) -> Result<(), EngineError> {
+    let Action::Deposit { amount, asset } = row.action else {
+        return Err(EngineError::InvalidTransactionType);
+    };
+    let fee = e.fees.fee_for(row.kind(), row.client, amount)?;
+    limits::check_deposit(e, row.client, asset, amount)?;
 
-pub fn execute(e: &mut Engine, transaction: &Transaction) -> Result<(), EngineError> {
     e.clients
-        .entry(transaction.client)
-        .or_insert(Client::new(transaction.client))
-        .deposit_funds(transaction.amount)
-        .and_then(|_| Ok(e.transactions.insert(transaction.tx, *transaction)))
-        .map(|_| ())
+        .entry(row.client)
+        .or_insert(Client::new(row.client))
+        .active()?
+        .deposit_funds(asset, amount)?;
+    fees::charge(e, row.client, asset, fee)?;
+
+    if let Some(transaction) = Transaction::from_row(row) {
+        e.transactions
+            .insert(row.tx, Transaction { fee, ..transaction });
+    }
+    Ok(())
+}
+
+/// What a dispute does when the deposit was already (partly) spent
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum NegativeBalancePolicy {
+    /// Hold the whole amount, available funds may go negative without bound
+    #[default]
+    Allow,
+    /// Reject disputes that would exceed the spendable funds
+    Reject,
+    /// Hold what can be spent, the rest is recorded as a shortfall which a
+    /// chargeback turns into a receivable
+    Cap,
+}
+
+impl NegativeBalancePolicy {
+    pub fn name(&self) -> &str {
+        match self {
+            NegativeBalancePolicy::Allow => "allow",
+            NegativeBalancePolicy::Reject => "reject",
+            NegativeBalancePolicy::Cap => "cap",
+        }
+    }
+}
+
+impl FromStr for NegativeBalancePolicy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "allow" => Ok(NegativeBalancePolicy::Allow),
+            "reject" => Ok(NegativeBalancePolicy::Reject),
+            "cap" => Ok(NegativeBalancePolicy::Cap),
+            _ => Err(format!(
+                "Unknown negative balance policy {}, expected allow, reject or cap.",
+                s
+            )),
+        }
+    }
 }
 
-pub fn dispute(client: &mut Client, transaction: &mut Transaction) -> Result<(), EngineError> {
-    transaction.assure_status(TransactionDisputeStatus::NONE)?;
+pub fn dispute(
+    client: &mut Client,
+    transaction: &mut Transaction,
+    requested: Option<Amount>,
+    rules: DisputeRules,
+    policy: NegativeBalancePolicy,
+) -> Result<(), EngineError> {
+    rules.dispute.check(transaction.kind)?;
+    let amount = transaction.dispute_amount(requested)?;
+    if rules.dispute == DisputePolicy::Record {
+        transaction.record_dispute(amount)?;
+        return Ok(());
+    }
+
+    let client = client.active()?;
+
+    let held = match policy {
+        NegativeBalancePolicy::Allow => amount,
+        NegativeBalancePolicy::Reject => {
+            // A hold is not a debit, only the amount is checked
+            let spendable = client.spendable_funds(transaction.asset)?;
+            if spendable < amount {
+                return Err(EngineError::InsufficientFunds(
+                    client.id(),
+                    spendable,
+                    amount,
+                ));
+            }
+            amount
+        }
+        NegativeBalancePolicy::Cap => client
+            .spendable_funds(transaction.asset)?
+            .clamp(Amount::zero(), amount),
+    };
+    client.hold_funds(transaction.asset, held)?;
 
-    client.hold_funds(transaction.amount)?;
-    transaction.dispute_status = TransactionDisputeStatus::DISPUTED;
+    transaction.record_dispute(amount)?;
+    transaction.record_shortfall(amount.checked_sub(held)?)?;
 
     Ok(())
 }
 
-pub fn resolve(client: &mut Client, transaction: &mut Transaction) -> Result<(), EngineError> {
-    transaction.assure_status(TransactionDisputeStatus::DISPUTED)?;
+/// Only held funds go back, a shortfall is simply dropped
+pub fn resolve(
+    client: &mut Client,
+    transaction: &mut Transaction,
+    requested: Option<Amount>,
+    rules: DisputeRules,
+) -> Result<(), EngineError> {
+    rules.resolve.check(transaction.kind)?;
+    let amount = transaction.settle_amount(requested)?;
+    if rules.dispute == DisputePolicy::Record {
+        transaction.record_resolve(amount)?;
+        return Ok(());
+    }
+
+    let (held, shortfall) = transaction.split_shortfall(amount)?;
 
-    client.release_funds(transaction.amount)?;
-    transaction.dispute_status = TransactionDisputeStatus::NONE;
+    client.release_funds(transaction.asset, held)?;
+    transaction.record_resolved_shortfall(shortfall)?;
+    transaction.record_resolve(amount)?;
 
     Ok(())
 }
 
-pub fn revert(client: &mut Client, transaction: &mut Transaction) -> Result<(), EngineError> {
-    transaction.assure_status(TransactionDisputeStatus::DISPUTED)?;
+/// Held funds are charged back, a shortfall becomes a receivable. Without a
+/// hold the amount is taken from the available funds directly, as far as the
+/// negative balance policy allows.
+pub fn revert(
+    client: &mut Client,
+    transaction: &mut Transaction,
+    requested: Option<Amount>,
+    rules: DisputeRules,
+    policy: NegativeBalancePolicy,
+) -> Result<(), EngineError> {
+    let amount = transaction.settle_amount(requested)?;
+    let shortfall = match rules.dispute {
+        DisputePolicy::Record => {
+            let spendable = client.spendable_funds(transaction.asset)?;
+            let taken = match policy {
+                NegativeBalancePolicy::Allow => amount,
+                NegativeBalancePolicy::Reject if spendable < amount => {
+                    return Err(EngineError::InsufficientFunds(
+                        client.id(),
+                        spendable,
+                        amount,
+                    ));
+                }
+                NegativeBalancePolicy::Reject => amount,
+                NegativeBalancePolicy::Cap => spendable.clamp(Amount::zero(), amount),
+            };
+            client.take_funds(transaction.asset, taken)?;
 
-    client.chargeback_funds(transaction.amount)?;
-    transaction.dispute_status = TransactionDisputeStatus::REVERSED;
+            let shortfall = amount.checked_sub(taken)?;
+            transaction.record_shortfall(shortfall)?;
+            shortfall
+        }
+        _ => {
+            let (held, shortfall) = transaction.split_shortfall(amount)?;
+            client.discard_held_funds(transaction.asset, held)?;
+            shortfall
+        }
+    };
+    rules.chargeback.apply(client)?;
+    client.add_receivable(transaction.asset, shortfall)?;
+    transaction.record_charged_back_shortfall(shortfall)?;
+    transaction.record_chargeback(amount)?;
 
     Ok(())
 }
 
+pub struct Handler;
+
+impl TransactionHandler for Handler {
+    fn is_stored(&self) -> bool {
+        true
+    }
+
+    fn execute(&self, e: &mut Engine, row: &Row) -> Result<(), EngineError> {
+        execute(e, row)
+    }
+
+    fn dispute(&self, e: &mut Engine, row: &Reference) -> Result<(), EngineError> {
+        let rules = e.config.dispute_rules(TransactionType::DEPOSIT)?;
+        let policy = e.config.negative_balance;
+        let (target, client) = e.get_mut_transaction_client_pair(row.tx, row.client)?;
+
+        dispute(client, target, row.amount, rules, policy)
+    }
+
+    fn resolve(&self, e: &mut Engine, row: &Reference) -> Result<(), EngineError> {
+        let rules = e.config.dispute_rules(TransactionType::DEPOSIT)?;
+        let (target, client) = e.get_mut_transaction_client_pair(row.tx, row.client)?;
+
+        resolve(client, target, row.amount, rules)
+    }
+
+    fn revert(&self, e: &mut Engine, row: &Reference) -> Result<(), EngineError> {
+        let rules = e.config.dispute_rules(TransactionType::DEPOSIT)?;
+        let policy = e.config.negative_balance;
+        let (target, client) = e.get_mut_transaction_client_pair(row.tx, row.client)?;
+
+        revert(client, target, row.amount, rules, policy)
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use crate::{
+        asset::Asset,
+        client::Freeze,
         decimal::Decimal,
-        engine::{deposit::execute, Engine},
-        transaction::{Transaction, TransactionType},
+        engine::{
+            deposit::execute,
+            testing::{amount_row, row},
+            ChargebackPolicy, DisputePolicy, DisputeRules, Engine, EngineConfig,
+            NegativeBalancePolicy, ResolvePolicy,
+        },
+        errors::EngineError,
+        transaction::{DisputedAmounts, TransactionType},
     };
 
     #[test]
     fn correct_execution() {
-        let mut e = Engine::new();
+        let mut e = Engine::default();
 
         execute(
             &mut e,
-            &Transaction::new(TransactionType::DEPOSIT, 1, 1, Decimal::from(100)),
+            &amount_row(TransactionType::DEPOSIT, 1, 1, Decimal::from(100)),
+        )
+        .unwrap();
+
+        assert_eq!(
+            e.get_client(1)
+                .unwrap()
+                .get_funds(Asset::default())
+                .available,
+            Decimal::from(100)
+        );
+    }
+
+    #[test]
+    fn partial_dispute() {
+        let mut e = Engine::default();
+
+        e.execute(&row(TransactionType::DEPOSIT, 1, 1, 100))
+            .unwrap();
+        e.execute(&row(TransactionType::DISPUTE, 1, 1, 30)).unwrap();
+        assert!(matches!(
+            e.execute(&row(TransactionType::DISPUTE, 1, 1, 80)),
+            Err(EngineError::DisputeAmountTooLarge(1, _))
+        ));
+        e.execute(&row(TransactionType::RESOLVE, 1, 1, 10)).unwrap();
+
+        let funds = e.get_client(1).unwrap().get_funds(Asset::default());
+        assert_eq!(funds.available, Decimal::from(80));
+        assert_eq!(funds.held, Decimal::from(20));
+
+        // No amount settles everything that is still disputed
+        e.execute(&row(TransactionType::CHARGEBACK, 1, 1, 0))
+            .unwrap();
+
+        let funds = e.get_client(1).unwrap().get_funds(Asset::default());
+        assert_eq!(funds.available, Decimal::from(80));
+        assert_eq!(funds.held, Decimal::zero());
+        assert_eq!(
+            e.get_transaction(1).unwrap().disputed_amounts,
+            DisputedAmounts {
+                disputed: Decimal::zero(),
+                resolved: Decimal::from(10),
+                charged_back: Decimal::from(20),
+                ..Default::default()
+            }
+        );
+    }
+
+    #[test]
+    fn partial_chargebacks() {
+        let mut e = Engine::default();
+
+        e.execute(&row(TransactionType::DEPOSIT, 1, 1, 100))
+            .unwrap();
+        e.execute(&row(TransactionType::DISPUTE, 1, 1, 0)).unwrap();
+        e.execute(&row(TransactionType::CHARGEBACK, 1, 1, 40))
+            .unwrap();
+        assert!(e.get_client(1).unwrap().is_locked());
+
+        // The rest of the dispute is still settled on the locked account
+        e.execute(&row(TransactionType::CHARGEBACK, 1, 1, 20))
+            .unwrap();
+        e.execute(&row(TransactionType::RESOLVE, 1, 1, 0)).unwrap();
+
+        let client = e.get_client(1).unwrap();
+        assert!(client.is_locked());
+        assert_eq!(
+            client.get_funds(Asset::default()).available,
+            Decimal::from(40)
+        );
+        assert_eq!(client.get_funds(Asset::default()).held, Decimal::zero());
+        assert_eq!(
+            e.get_transaction(1).unwrap().disputed_amounts,
+            DisputedAmounts {
+                disputed: Decimal::zero(),
+                resolved: Decimal::from(40),
+                charged_back: Decimal::from(60),
+                ..Default::default()
+            }
+        );
+        assert!(matches!(
+            e.execute(&row(TransactionType::DISPUTE, 1, 1, 0)),
+            Err(EngineError::AccountLocked(1))
+        ));
+    }
+
+    /// Deposits 100, spends `spent` of it and disputes the deposit
+    fn spent_deposit(
+        policy: NegativeBalancePolicy,
+        spent: i64,
+    ) -> (Engine, Result<(), EngineError>) {
+        let mut e = Engine::new(EngineConfig::default().with_negative_balance_policy(policy));
+
+        e.execute(&row(TransactionType::DEPOSIT, 1, 1, 100))
+            .unwrap();
+        e.execute(&row(TransactionType::WITHDRAWAL, 1, 2, spent))
+            .unwrap();
+        let result = e.execute(&row(TransactionType::DISPUTE, 1, 1, 0));
+
+        (e, result)
+    }
+
+    #[test]
+    fn negative_balance_allowed() {
+        let (e, result) = spent_deposit(NegativeBalancePolicy::Allow, 100);
+
+        result.unwrap();
+        let funds = e.get_client(1).unwrap().get_funds(Asset::default());
+        assert_eq!(funds.available, Decimal::from(-100));
+        assert_eq!(funds.held, Decimal::from(100));
+    }
+
+    #[test]
+    fn negative_balance_rejected() {
+        let (mut e, result) = spent_deposit(NegativeBalancePolicy::Reject, 100);
+
+        assert!(matches!(
+            result,
+            Err(EngineError::InsufficientFunds(1, _, _))
+        ));
+        let funds = e.get_client(1).unwrap().get_funds(Asset::default());
+        assert_eq!(funds.available, Decimal::zero());
+        assert_eq!(funds.held, Decimal::zero());
+        assert_eq!(
+            e.get_transaction(1).unwrap().disputed_amounts,
+            DisputedAmounts::default()
+        );
+
+        // A credit line covers the dispute
+        e.set_credit_limit(1, Decimal::from(100)).unwrap();
+        e.execute(&amount_row(TransactionType::DISPUTE, 1, 1, Decimal::zero()))
+            .unwrap();
+    }
+
+    #[test]
+    fn rejecting_policy_holds_under_debits_freeze() {
+        let mut e = Engine::new(
+            EngineConfig::default().with_negative_balance_policy(NegativeBalancePolicy::Reject),
+        );
+        e.execute(&row(TransactionType::DEPOSIT, 1, 1, 100))
+            .unwrap();
+        e.execute_admin(
+            &row(TransactionType::FREEZE, 1, 2, 0)
+                .with_freeze(Freeze::Debits)
+                .with_reason("AML".parse().unwrap()),
         )
         .unwrap();
 
+        // A hold is not a debit
+        e.execute(&row(TransactionType::DISPUTE, 1, 1, 0)).unwrap();
+        let funds = e.get_client(1).unwrap().get_funds(Asset::default());
+        assert_eq!(funds.available, Decimal::zero());
+        assert_eq!(funds.held, Decimal::from(100));
+    }
+
+    #[test]
+    fn negative_balance_capped() {
+        let (mut e, result) = spent_deposit(NegativeBalancePolicy::Cap, 70);
+
+        result.unwrap();
+        let funds = e.get_client(1).unwrap().get_funds(Asset::default());
+        assert_eq!(funds.available, Decimal::zero());
+        assert_eq!(funds.held, Decimal::from(30));
         assert_eq!(
-            e.get_client(1).unwrap().get_funds().available,
+            e.get_transaction(1).unwrap().disputed_amounts.shortfall,
+            Decimal::from(70)
+        );
+
+        // Held funds are settled before the shortfall
+        e.execute(&row(TransactionType::RESOLVE, 1, 1, 50)).unwrap();
+        let funds = e.get_client(1).unwrap().get_funds(Asset::default());
+        assert_eq!(funds.available, Decimal::from(30));
+        assert_eq!(funds.held, Decimal::zero());
+
+        e.execute(&row(TransactionType::CHARGEBACK, 1, 1, 0))
+            .unwrap();
+        let client = e.get_client(1).unwrap();
+        assert!(client.is_locked());
+        assert_eq!(
+            client.get_funds(Asset::default()).available,
+            Decimal::from(30)
+        );
+        assert_eq!(
+            e.get_transaction(1).unwrap().disputed_amounts,
+            DisputedAmounts {
+                disputed: Decimal::zero(),
+                resolved: Decimal::from(50),
+                charged_back: Decimal::from(50),
+                shortfall: Decimal::zero(),
+                receivable: Decimal::from(50),
+            }
+        );
+        assert_eq!(client.get_receivable(Asset::default()), Decimal::from(50));
+    }
+
+    #[test]
+    fn recorded_dispute_of_spent_deposit() {
+        let rules = DisputeRules {
+            dispute: DisputePolicy::Record,
+            resolve: ResolvePolicy::Reinstate,
+            chargeback: ChargebackPolicy::Lock,
+        };
+
+        for (policy, available, receivable) in [
+            (NegativeBalancePolicy::Allow, Some(-70), 0),
+            (NegativeBalancePolicy::Reject, None, 0),
+            (NegativeBalancePolicy::Cap, Some(0), 70),
+        ] {
+            let config = EngineConfig::default()
+                .with_dispute_rules(TransactionType::DEPOSIT, rules)
+                .with_negative_balance_policy(policy);
+            let mut e = Engine::new(config);
+
+            e.execute(&row(TransactionType::DEPOSIT, 1, 1, 100))
+                .unwrap();
+            e.execute(&row(TransactionType::WITHDRAWAL, 1, 2, 70))
+                .unwrap();
+            e.execute(&row(TransactionType::DISPUTE, 1, 1, 0)).unwrap();
+            let result = e.execute(&row(TransactionType::CHARGEBACK, 1, 1, 0));
+
+            let client = e.get_client(1).unwrap();
+            match available {
+                Some(available) => {
+                    result.unwrap();
+                    assert_eq!(
+                        client.get_funds(Asset::default()).available,
+                        Decimal::from(available)
+                    );
+                }
+                None => {
+                    assert!(matches!(
+                        result,
+                        Err(EngineError::InsufficientFunds(1, _, _))
+                    ));
+                    assert!(!client.is_locked());
+                }
+            }
+            assert_eq!(
+                client.get_receivable(Asset::default()),
+                Decimal::from(receivable)
+            );
+        }
+    }
+
+    #[test]
+    fn unresolvable_disputes() {
+        let rules = DisputeRules {
+            dispute: DisputePolicy::Hold,
+            resolve: ResolvePolicy::Reject,
+            chargeback: ChargebackPolicy::KeepActive,
+        };
+        let mut e = Engine::new(
+            EngineConfig::default().with_dispute_rules(TransactionType::DEPOSIT, rules),
+        );
+
+        e.execute(&row(TransactionType::DEPOSIT, 1, 1, 100))
+            .unwrap();
+        e.execute(&row(TransactionType::DISPUTE, 1, 1, 0)).unwrap();
+        assert_eq!(
+            e.execute(&row(TransactionType::RESOLVE, 1, 1, 0)),
+            Err(EngineError::ResolveRejected(TransactionType::DEPOSIT))
+        );
+        e.execute(&row(TransactionType::CHARGEBACK, 1, 1, 0))
+            .unwrap();
+        assert!(!e.get_client(1).unwrap().is_locked());
+    }
+
+    #[test]
+    fn duplicate_ids() {
+        let mut e = Engine::default();
+        let deposit = amount_row(TransactionType::DEPOSIT, 1, 1, Decimal::from(100));
+
+        e.execute(&deposit).unwrap();
+        e.execute(&deposit).unwrap();
+        assert!(matches!(
+            e.execute(&amount_row(
+                TransactionType::DEPOSIT,
+                1,
+                1,
+                Decimal::from(5)
+            )),
+            Err(EngineError::DuplicateTransaction(1))
+        ));
+        assert!(matches!(
+            e.execute(&amount_row(
+                TransactionType::WITHDRAWAL,
+                1,
+                1,
+                Decimal::from(100)
+            )),
+            Err(EngineError::DuplicateTransaction(1))
+        ));
+
+        assert_eq!(
+            e.get_client(1)
+                .unwrap()
+                .get_funds(Asset::default())
+                .available,
             Decimal::from(100)
         );
+        assert_eq!(e.get_transaction(1).unwrap().amount, Decimal::from(100));
+
+        // Ids of rejected rows can't be reused, not even by the same row
+        let withdrawal = amount_row(TransactionType::WITHDRAWAL, 1, 2, Decimal::from(500));
+        assert!(matches!(
+            e.execute(&withdrawal),
+            Err(EngineError::InsufficientFunds(1, _, _))
+        ));
+        e.execute(&amount_row(
+            TransactionType::DEPOSIT,
+            1,
+            3,
+            Decimal::from(500),
+        ))
+        .unwrap();
+        assert!(matches!(
+            e.execute(&withdrawal),
+            Err(EngineError::DuplicateTransaction(2))
+        ));
+        assert!(e.get_transaction(2).is_none());
     }
 }
@@ -1,12 +1,67 @@
-use super::{deposit, withdrawal, Engine};
-use crate::{errors::EngineError, transaction::Transaction};
+use super::{Engine, Reference};
+use crate::{errors::EngineError, row::Row};
 
-pub fn execute(e: &mut Engine, transaction: &Transaction) -> Result<(), EngineError> {
-    let (target, client) = e.get_mut_transaction_client_pair(transaction.tx)?;
+/// Acts on the referenced transaction through the handler of its type
+pub fn execute(e: &mut Engine, row: &Row) -> Result<(), EngineError> {
+    let reference = Reference::from_row(row)?;
+    let kind = e
+        .get_transaction(reference.tx)
+        .ok_or(EngineError::TransactionNotFound(reference.tx))?
+        .kind;
 
-    match target.kind {
-        crate::transaction::TransactionType::DEPOSIT => deposit::dispute(client, target),
-        crate::transaction::TransactionType::WITHDRAWAL => withdrawal::dispute(client, target),
-        _ => Err(EngineError::InvalidTransactionType),
+    e.handlers.get(kind)?.dispute(e, &reference)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        engine::{
+            testing::{funds, row},
+            Engine,
+        },
+        errors::EngineError,
+        transaction::TransactionType,
+    };
+
+    /// Client 2 can't dispute, resolve or charge back transactions of client 1
+    #[test]
+    fn only_owner_can_settle() {
+        let deposit = row(TransactionType::DEPOSIT, 1, 1, 10);
+        let dispute = row(TransactionType::DISPUTE, 1, 1, 0);
+        let transfer = row(TransactionType::TRANSFER, 1, 2, 10).with_destination(2);
+        let cases = [
+            (vec![deposit], TransactionType::DISPUTE, 1),
+            (vec![deposit, dispute], TransactionType::RESOLVE, 1),
+            (vec![deposit, dispute], TransactionType::CHARGEBACK, 1),
+            (vec![deposit, transfer], TransactionType::DISPUTE, 2),
+        ];
+
+        for (setup, kind, tx) in cases {
+            let mut e = Engine::default();
+            for t in &setup {
+                e.execute(t).unwrap();
+            }
+            let before = funds(&e, 1);
+
+            assert_eq!(
+                e.execute(&row(kind, 2, tx, 0)),
+                Err(EngineError::TransactionNotOwned(tx, 2)),
+                "{} of tx {}",
+                kind,
+                tx
+            );
+            assert_eq!(funds(&e, 1), before);
+            assert!(!e.get_client(1).unwrap().is_locked());
+        }
+    }
+
+    #[test]
+    fn unknown_transaction() {
+        let mut e = Engine::default();
+
+        assert_eq!(
+            e.execute(&row(TransactionType::DISPUTE, 1, 3, 0)),
+            Err(EngineError::TransactionNotFound(3))
+        );
     }
 }
@@ -0,0 +1,241 @@
+use crate::{
+    asset::Asset,
+    client::{Client, ClientId, Funds, Operation},
+    errors::EngineError,
+    transaction::{TransactionId, TransactionType},
+};
+
+/**
+ * What an executed row did to the accounts. Every fund operation is one
+ * event, named after the operation rather than the row: a withdrawal with a
+ * fee withdraws twice, and the source of a charged back transfer is credited
+ * with a deposit. Expired authorizations release their funds under the id of
+ * the authorization.
+ */
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Event {
+    FundsDeposited(BalanceChange),
+    FundsWithdrawn(BalanceChange),
+    FundsHeld(BalanceChange),
+    FundsReleased(BalanceChange),
+    ChargedBack(BalanceChange),
+    AccountLocked {
+        tx: TransactionId,
+        client: ClientId,
+    },
+    AccountUnlocked {
+        tx: TransactionId,
+        client: ClientId,
+    },
+    /// Nothing changed, the row failed with `error`
+    TransactionRejected {
+        tx: TransactionId,
+        client: ClientId,
+        kind: TransactionType,
+        error: EngineError,
+    },
+}
+
+/// Balances of one asset of `client` before and after row `tx`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BalanceChange {
+    pub tx: TransactionId,
+    pub client: ClientId,
+    pub asset: Asset,
+    pub before: Funds,
+    pub after: Funds,
+}
+
+/// Receives the events of every executed row, in order
+pub trait EventSink {
+    fn publish(&mut self, event: &Event);
+}
+
+impl<F: FnMut(&Event)> EventSink for F {
+    fn publish(&mut self, event: &Event) {
+        self(event)
+    }
+}
+
+/// One event per fund operation run on `client` since `Client::begin`, in
+/// the order they ran
+pub fn balance_changes(tx: TransactionId, client: &Client) -> Vec<Event> {
+    client
+        .changes()
+        .map(|change| {
+            let balance = BalanceChange {
+                tx,
+                client: client.id(),
+                asset: change.asset,
+                before: change.before,
+                after: change.after,
+            };
+            match change.operation {
+                Operation::Deposit => Event::FundsDeposited(balance),
+                Operation::Withdrawal => Event::FundsWithdrawn(balance),
+                Operation::Hold => Event::FundsHeld(balance),
+                Operation::Release => Event::FundsReleased(balance),
+                Operation::Chargeback => Event::ChargedBack(balance),
+            }
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{BalanceChange, Event};
+    use crate::{
+        asset::Asset,
+        client::Funds,
+        decimal::Decimal,
+        engine::{
+            testing::row, ChargebackPolicy, DisputePolicy, DisputeRules, Engine, EngineConfig,
+            ResolvePolicy,
+        },
+        errors::EngineError,
+        transaction::TransactionType,
+    };
+    use std::sync::{Arc, Mutex};
+
+    fn funds(available: i64, held: i64) -> Funds {
+        Funds {
+            available: Decimal::from(available),
+            held: Decimal::from(held),
+        }
+    }
+
+    fn change(tx: u32, before: Funds, after: Funds) -> BalanceChange {
+        BalanceChange {
+            tx,
+            client: 1,
+            asset: Asset::default(),
+            before,
+            after,
+        }
+    }
+
+    fn record(e: &mut Engine) -> Arc<Mutex<Vec<Event>>> {
+        let events = Arc::new(Mutex::new(Vec::new()));
+        let sink = events.clone();
+        e.subscribe(move |event: &Event| sink.lock().unwrap().push(*event));
+        events
+    }
+
+    #[test]
+    fn dispute_lifecycle() {
+        let mut e = Engine::default();
+        let events = record(&mut e);
+
+        e.execute(&row(TransactionType::DEPOSIT, 1, 1, 100))
+            .unwrap();
+        e.execute(&row(TransactionType::DISPUTE, 1, 1, 0)).unwrap();
+        e.execute(&row(TransactionType::CHARGEBACK, 1, 1, 0))
+            .unwrap();
+        assert!(e
+            .execute(&row(TransactionType::WITHDRAWAL, 1, 2, 5))
+            .is_err());
+
+        assert_eq!(
+            *events.lock().unwrap(),
+            vec![
+                Event::FundsDeposited(change(1, funds(0, 0), funds(100, 0))),
+                Event::FundsHeld(change(1, funds(100, 0), funds(0, 100))),
+                Event::ChargedBack(change(1, funds(0, 100), funds(0, 0))),
+                Event::AccountLocked { tx: 1, client: 1 },
+                Event::TransactionRejected {
+                    tx: 2,
+                    client: 1,
+                    kind: TransactionType::WITHDRAWAL,
+                    error: EngineError::AccountLocked(1),
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn transfer_moves_funds() {
+        let mut e = Engine::default();
+
+        e.execute(&row(TransactionType::DEPOSIT, 1, 1, 10)).unwrap();
+        let events = record(&mut e);
+        e.execute(&row(TransactionType::TRANSFER, 1, 2, 4).with_destination(2))
+            .unwrap();
+
+        assert_eq!(
+            *events.lock().unwrap(),
+            vec![
+                Event::FundsWithdrawn(change(2, funds(10, 0), funds(6, 0))),
+                Event::FundsDeposited(BalanceChange {
+                    client: 2,
+                    ..change(2, funds(0, 0), funds(4, 0))
+                }),
+            ]
+        );
+    }
+
+    #[test]
+    fn events_follow_operations() {
+        let rules = DisputeRules {
+            dispute: DisputePolicy::Hold,
+            resolve: ResolvePolicy::Reinstate,
+            chargeback: ChargebackPolicy::KeepActive,
+        };
+        let mut e = Engine::new(
+            EngineConfig::default().with_dispute_rules(TransactionType::WITHDRAWAL, rules),
+        );
+
+        e.execute(&row(TransactionType::DEPOSIT, 1, 1, 10)).unwrap();
+        e.execute(&row(TransactionType::TRANSFER, 1, 2, 4).with_destination(2))
+            .unwrap();
+        e.execute(&row(TransactionType::WITHDRAWAL, 1, 3, 2))
+            .unwrap();
+        let events = record(&mut e);
+
+        // The withdrawn funds come back to be held
+        e.execute(&row(TransactionType::DISPUTE, 1, 3, 0)).unwrap();
+        // The source gets its funds back, only the destination is charged back
+        e.execute(&row(TransactionType::DISPUTE, 1, 2, 0)).unwrap();
+        e.execute(&row(TransactionType::CHARGEBACK, 1, 2, 0))
+            .unwrap();
+
+        let to = |client, before, after| BalanceChange {
+            client,
+            ..change(2, before, after)
+        };
+        assert_eq!(
+            *events.lock().unwrap(),
+            vec![
+                Event::FundsDeposited(change(3, funds(4, 0), funds(6, 0))),
+                Event::FundsHeld(change(3, funds(6, 0), funds(4, 2))),
+                Event::FundsHeld(to(2, funds(4, 0), funds(0, 4))),
+                Event::FundsDeposited(change(2, funds(4, 2), funds(8, 2))),
+                Event::AccountLocked { tx: 2, client: 1 },
+                Event::ChargedBack(to(2, funds(0, 4), funds(0, 0))),
+            ]
+        );
+    }
+
+    #[test]
+    fn expiry_releases_authorization() {
+        let mut e = Engine::default();
+
+        e.execute(&row(TransactionType::DEPOSIT, 1, 1, 10)).unwrap();
+        e.execute(
+            &row(TransactionType::AUTHORIZE, 1, 2, 6)
+                .with_timestamp(10)
+                .with_expiry(20),
+        )
+        .unwrap();
+        let events = record(&mut e);
+        e.execute(&row(TransactionType::DEPOSIT, 1, 3, 1).with_timestamp(20))
+            .unwrap();
+
+        assert_eq!(
+            *events.lock().unwrap(),
+            vec![
+                Event::FundsReleased(change(2, funds(4, 6), funds(10, 0))),
+                Event::FundsDeposited(change(3, funds(10, 0), funds(11, 0))),
+            ]
+        );
+    }
+}
@@ -0,0 +1,356 @@
+/**
+ * Fees charged on top of transactions, configured per transaction type and
+ * optionally per client tier. Fees are moved to the house account together
+ * with the transaction they belong to. The house account has to be named
+ * before any fee is set and its id is reserved: rows can't use it.
+ */
+use super::{Engine, Reference};
+use crate::{
+    asset::Asset,
+    client::{Client, ClientId},
+    decimal::{Amount, Decimal, RoundingMode},
+    errors::EngineError,
+    short_code,
+    transaction::TransactionType,
+};
+use serde::Deserialize;
+use std::{collections::HashMap, error::Error, io::Read};
+
+short_code!(Tier, 16, EngineError::InvalidTier);
+
+pub type FeeRate = Decimal<6>;
+
+/// `flat + rate * amount`, rounded half up
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Fee {
+    pub flat: Amount,
+    pub rate: FeeRate,
+}
+
+/// What happens to the fee of a transaction that gets charged back
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum FeeRefund {
+    #[default]
+    Never,
+    OnChargeback,
+}
+
+#[derive(Debug, Clone)]
+pub struct FeeSchedule {
+    house: Option<ClientId>,
+    refund: FeeRefund,
+    fees: HashMap<(TransactionType, Option<Tier>), Fee>,
+    tiers: HashMap<ClientId, Tier>,
+}
+
+/// No house account and no fees
+impl Default for FeeSchedule {
+    fn default() -> Self {
+        Self {
+            house: None,
+            refund: FeeRefund::default(),
+            fees: HashMap::new(),
+            tiers: HashMap::new(),
+        }
+    }
+}
+
+#[derive(Deserialize)]
+struct FeeRow {
+    #[serde(rename = "type")]
+    kind: TransactionType,
+    #[serde(default)]
+    tier: Option<Tier>,
+    /// Either part may be left empty, which makes it zero
+    #[serde(default)]
+    flat: Option<Amount>,
+    #[serde(default)]
+    rate: Option<FeeRate>,
+}
+
+#[derive(Deserialize)]
+struct TierRow {
+    client: ClientId,
+    tier: Tier,
+}
+
+impl FeeSchedule {
+    pub fn new(house: ClientId) -> Self {
+        Self {
+            house: Some(house),
+            ..Self::default()
+        }
+    }
+
+    pub fn with_refund(self, refund: FeeRefund) -> Self {
+        Self { refund, ..self }
+    }
+
+    pub fn house(&self) -> Option<ClientId> {
+        self.house
+    }
+
+    /// A fee without tier applies to clients for which no tier fee is set.
+    /// Fees can't be negative.
+    pub fn set_fee(
+        &mut self,
+        kind: TransactionType,
+        tier: Option<Tier>,
+        fee: Fee,
+    ) -> Result<(), EngineError> {
+        if self.house.is_none() {
+            return Err(EngineError::MissingHouse);
+        }
+        if fee.flat < Amount::zero() || fee.rate < FeeRate::zero() {
+            return Err(EngineError::InvalidFee(kind));
+        }
+
+        self.fees.insert((kind, tier), fee);
+        Ok(())
+    }
+
+    pub fn set_tier(&mut self, client: ClientId, tier: Tier) {
+        self.tiers.insert(client, tier);
+    }
+
+    /// Reads `type,tier,flat,rate` rows
+    pub fn load_fees<R: Read>(&mut self, reader: R) -> Result<(), Box<dyn Error>> {
+        for row in csv::Reader::from_reader(reader).deserialize() {
+            let row: FeeRow = row?;
+            self.set_fee(
+                row.kind,
+                row.tier,
+                Fee {
+                    flat: row.flat.unwrap_or(Amount::zero()),
+                    rate: row.rate.unwrap_or(FeeRate::zero()),
+                },
+            )?;
+        }
+
+        Ok(())
+    }
+
+    /// Reads `client,tier` rows
+    pub fn load_tiers<R: Read>(&mut self, reader: R) -> Result<(), Box<dyn Error>> {
+        for row in csv::Reader::from_reader(reader).deserialize() {
+            let row: TierRow = row?;
+            self.set_tier(row.client, row.tier);
+        }
+
+        Ok(())
+    }
+
+    pub fn fee_for(
+        &self,
+        kind: TransactionType,
+        client: ClientId,
+        amount: Amount,
+    ) -> Result<Amount, EngineError> {
+        let tier = self.tiers.get(&client).copied();
+        let fee = self
+            .fees
+            .get(&(kind, tier))
+            .or_else(|| self.fees.get(&(kind, None)));
+
+        match fee {
+            Some(fee) => amount
+                .checked_mul(fee.rate, RoundingMode::HalfUp)
+                .and_then(|variable| variable.value.checked_add(fee.flat))
+                .map_err(|_| EngineError::Overflow(client)),
+            None => Ok(Amount::zero()),
+        }
+    }
+}
+
+/// Moves `fee` from `client` to the house account
+pub fn charge(
+    e: &mut Engine,
+    client: ClientId,
+    asset: Asset,
+    fee: Amount,
+) -> Result<(), EngineError> {
+    if fee == Amount::zero() {
+        return Ok(());
+    }
+
+    e.clients
+        .get_mut(&client)
+        .ok_or(EngineError::ClientNotFound(client))?
+        .active()?
+        .withdraw_funds(asset, fee)?;
+
+    let house = e.fees.house.ok_or(EngineError::MissingHouse)?;
+    e.clients
+        .entry(house)
+        .or_insert(Client::new(house))
+        .active()?
+        .deposit_funds(asset, fee)?;
+
+    Ok(())
+}
+
+/// Gives back the fee of the transaction referenced by a chargeback row,
+/// if the schedule says so. Partial chargebacks keep the fee, it is refunded
+/// whole by the one that charges back the rest of the transaction.
+pub fn refund(e: &mut Engine, chargeback: &Reference) -> Result<(), EngineError> {
+    if e.fees.refund != FeeRefund::OnChargeback {
+        return Ok(());
+    }
+
+    let house = e.fees.house.ok_or(EngineError::MissingHouse)?;
+    let (target, client) = e.get_mut_transaction_client_pair(chargeback.tx, chargeback.client)?;
+    let (fee, asset) = (target.fee, target.asset);
+    if fee == Amount::zero() || target.disputed_amounts.charged_back < target.amount {
+        return Ok(());
+    }
+
+    target.fee = Amount::zero();
+    client.refund_funds(asset, fee)?;
+    e.clients
+        .get_mut(&house)
+        .ok_or(EngineError::ClientNotFound(house))?
+        .active()?
+        .withdraw_funds(asset, fee)?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Fee, FeeRefund, FeeSchedule};
+    use crate::{
+        decimal::Decimal,
+        engine::{
+            testing::{amount_row, available},
+            Engine,
+        },
+        errors::EngineError,
+        row::Row,
+        transaction::TransactionType,
+    };
+
+    const HOUSE: u16 = 1000;
+
+    fn schedule() -> FeeSchedule {
+        let mut fees = FeeSchedule::new(HOUSE);
+        fees.load_fees(
+            "type,tier,flat,rate\n\
+             withdrawal,,1,0.01\n\
+             withdrawal,GOLD,0,0\n\
+             deposit,,0.5,\n"
+                .as_bytes(),
+        )
+        .unwrap();
+        fees.load_tiers("client,tier\n2,GOLD\n".as_bytes()).unwrap();
+        fees
+    }
+
+    fn row(kind: TransactionType, client: u16, tx: u32, amount: &str) -> Row {
+        amount_row(kind, client, tx, amount.parse().unwrap())
+    }
+
+    #[test]
+    fn fees_go_to_house() {
+        let mut e = Engine::default().with_fees(schedule());
+
+        e.execute(&row(TransactionType::DEPOSIT, 1, 1, "100"))
+            .unwrap();
+        e.execute(&row(TransactionType::DEPOSIT, 2, 2, "100"))
+            .unwrap();
+        e.execute(&row(TransactionType::WITHDRAWAL, 1, 3, "50"))
+            .unwrap();
+        e.execute(&row(TransactionType::WITHDRAWAL, 2, 4, "50"))
+            .unwrap();
+
+        // 100 - 0.5 - 50 - (1 + 50 * 1%)
+        assert_eq!(available(&e, 1), "48".parse().unwrap());
+        assert_eq!(available(&e, 2), "49.5".parse().unwrap());
+        assert_eq!(available(&e, HOUSE), "2.5".parse().unwrap());
+    }
+
+    #[test]
+    fn fee_is_atomic_with_transaction() {
+        let mut e = Engine::default().with_fees(schedule());
+
+        e.execute(&row(TransactionType::DEPOSIT, 1, 1, "10"))
+            .unwrap();
+        assert!(e
+            .execute(&row(TransactionType::WITHDRAWAL, 1, 2, "9"))
+            .is_err());
+
+        assert_eq!(available(&e, 1), "9.5".parse().unwrap());
+        assert_eq!(available(&e, HOUSE), "0.5".parse().unwrap());
+    }
+
+    #[test]
+    fn refund_on_chargeback() {
+        let mut fees = FeeSchedule::new(HOUSE).with_refund(FeeRefund::OnChargeback);
+        fees.set_fee(
+            TransactionType::DEPOSIT,
+            None,
+            Fee {
+                flat: Decimal::from(1),
+                rate: Decimal::zero(),
+            },
+        )
+        .unwrap();
+        let mut e = Engine::default().with_fees(fees);
+
+        e.execute(&row(TransactionType::DEPOSIT, 1, 1, "10"))
+            .unwrap();
+        e.execute(&row(TransactionType::DISPUTE, 1, 1, "0"))
+            .unwrap();
+        e.execute(&row(TransactionType::CHARGEBACK, 1, 1, "0.1"))
+            .unwrap();
+
+        // A partial chargeback keeps the fee
+        assert_eq!(available(&e, 1), "-1".parse().unwrap());
+        assert_eq!(available(&e, HOUSE), Decimal::from(1));
+
+        e.execute(&row(TransactionType::CHARGEBACK, 1, 1, "0"))
+            .unwrap();
+
+        // 10 - 1 (fee) - 10 (held) + 1 (refund)
+        assert_eq!(available(&e, 1), Decimal::zero());
+        assert_eq!(available(&e, HOUSE), Decimal::zero());
+        assert!(e.get_client(1).unwrap().is_locked());
+    }
+
+    #[test]
+    fn invalid_fees() {
+        let fee = |flat: &str, rate: &str| Fee {
+            flat: flat.parse().unwrap(),
+            rate: rate.parse().unwrap(),
+        };
+
+        assert_eq!(
+            FeeSchedule::default().set_fee(TransactionType::DEPOSIT, None, fee("1", "0")),
+            Err(EngineError::MissingHouse)
+        );
+        let mut fees = FeeSchedule::new(HOUSE);
+        assert_eq!(
+            fees.set_fee(TransactionType::DEPOSIT, None, fee("-1", "0")),
+            Err(EngineError::InvalidFee(TransactionType::DEPOSIT))
+        );
+        assert!(fees
+            .load_fees("type,tier,flat,rate\nwithdrawal,,0,-0.01\n".as_bytes())
+            .is_err());
+    }
+
+    #[test]
+    fn house_is_reserved() {
+        let mut e = Engine::default().with_fees(schedule());
+
+        assert_eq!(
+            e.execute(&row(TransactionType::DEPOSIT, HOUSE, 1, "10")),
+            Err(EngineError::ReservedClient(HOUSE))
+        );
+        e.execute(&row(TransactionType::DEPOSIT, 1, 2, "10"))
+            .unwrap();
+        assert_eq!(
+            e.execute(&row(TransactionType::TRANSFER, 1, 3, "5").with_destination(HOUSE)),
+            Err(EngineError::ReservedClient(HOUSE))
+        );
+        assert_eq!(available(&e, HOUSE), "0.5".parse().unwrap());
+    }
+}
@@ -0,0 +1,105 @@
+use super::Engine;
+use crate::errors::EngineError;
+use crate::row::{Action, Row};
+
+/// Administrative freeze of an account, a reason is mandatory
+pub fn execute(e: &mut Engine, row: &Row) -> Result<(), EngineError> {
+    let Action::Freeze { freeze } = row.action else {
+        return Err(EngineError::InvalidTransactionType);
+    };
+    let reason = row.reason.ok_or(EngineError::MissingReason(row.tx))?;
+
+    e.clients
+        .get_mut(&row.client)
+        .ok_or(EngineError::ClientNotFound(row.client))?
+        .freeze(freeze, reason);
+    Ok(())
+}
+
+pub fn clear(e: &mut Engine, row: &Row) -> Result<(), EngineError> {
+    let Action::Unfreeze { freeze } = row.action else {
+        return Err(EngineError::InvalidTransactionType);
+    };
+
+    e.clients
+        .get_mut(&row.client)
+        .ok_or(EngineError::ClientNotFound(row.client))?
+        .unfreeze(freeze)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        client::Freeze,
+        decimal::Decimal,
+        engine::{
+            testing::{available, row},
+            Engine,
+        },
+        errors::EngineError,
+        transaction::TransactionType,
+    };
+
+    #[test]
+    fn debit_freeze_allows_incoming_funds() {
+        let mut e = Engine::default();
+        let reason = "AML".parse().unwrap();
+
+        e.execute(&row(TransactionType::DEPOSIT, 1, 1, 100))
+            .unwrap();
+        e.execute(&row(TransactionType::DEPOSIT, 2, 2, 100))
+            .unwrap();
+        assert!(matches!(
+            e.execute_admin(&row(TransactionType::FREEZE, 1, 3, 0).with_freeze(Freeze::Debits)),
+            Err(EngineError::MissingReason(3))
+        ));
+        e.execute_admin(
+            &row(TransactionType::FREEZE, 1, 3, 0)
+                .with_freeze(Freeze::Debits)
+                .with_reason(reason),
+        )
+        .unwrap();
+
+        assert!(matches!(
+            e.execute(&row(TransactionType::WITHDRAWAL, 1, 4, 10)),
+            Err(EngineError::AccountFrozen(1, Freeze::Debits, r)) if r == reason
+        ));
+        e.execute(&row(TransactionType::TRANSFER, 2, 5, 10).with_destination(1))
+            .unwrap();
+        e.execute(&row(TransactionType::DISPUTE, 1, 1, 0)).unwrap();
+        e.execute(&row(TransactionType::RESOLVE, 1, 1, 0)).unwrap();
+        assert_eq!(available(&e, 1), Decimal::from(110));
+
+        e.execute_admin(&row(TransactionType::UNFREEZE, 1, 6, 0).with_freeze(Freeze::Debits))
+            .unwrap();
+        e.execute(&row(TransactionType::WITHDRAWAL, 1, 7, 10))
+            .unwrap();
+        assert!(matches!(
+            e.execute_admin(&row(TransactionType::UNFREEZE, 1, 8, 0).with_freeze(Freeze::Debits)),
+            Err(EngineError::AccountNotFrozen(1, Freeze::Debits))
+        ));
+    }
+
+    #[test]
+    fn credit_freeze_blocks_incoming_transfers() {
+        let mut e = Engine::default();
+
+        e.execute(&row(TransactionType::DEPOSIT, 1, 1, 100))
+            .unwrap();
+        e.execute(&row(TransactionType::DEPOSIT, 2, 2, 100))
+            .unwrap();
+        e.execute_admin(
+            &row(TransactionType::FREEZE, 2, 3, 0)
+                .with_freeze(Freeze::Credits)
+                .with_reason("SANCTION".parse().unwrap()),
+        )
+        .unwrap();
+
+        assert!(e
+            .execute(&row(TransactionType::TRANSFER, 1, 4, 10).with_destination(2))
+            .is_err());
+        e.execute(&row(TransactionType::TRANSFER, 2, 5, 10).with_destination(1))
+            .unwrap();
+        assert_eq!(available(&e, 1), Decimal::from(110));
+    }
+}
@@ -0,0 +1,256 @@
+use super::{
+    authorize, capture, chargeback, credit, deposit, dispute, freeze, limits, resolve, transfer,
+    unlock, void, withdrawal, Engine,
+};
+use crate::client::ClientId;
+use crate::decimal::Amount;
+use crate::errors::EngineError;
+use crate::row::{Action, Row};
+use crate::transaction::{TransactionId, TransactionType};
+use std::{collections::HashMap, sync::Arc};
+
+/**
+ * What the engine does with one transaction type. `execute` applies a row of
+ * that type; `dispute`, `resolve` and `revert` are called with the dispute,
+ * resolve or chargeback row referencing a stored transaction of that type.
+ * Types that can't be disputed keep the defaults, which reject the row.
+ *
+ * When a handler fails, the accounts of the row's client, of the parties to
+ * the referenced transaction and of the house are rolled back, and the
+ * referenced transaction and authorization restored.
+ */
+pub trait TransactionHandler {
+    /// Whether executed transactions get stored and can be referenced later.
+    /// Rows of a stored type can't reuse the id of another transaction.
+    fn is_stored(&self) -> bool {
+        false
+    }
+
+    /// Whether rows of the type change account settings, which only rows
+    /// from the admin source (`Engine::execute_admin`) may do
+    fn is_admin(&self) -> bool {
+        false
+    }
+
+    fn execute(&self, e: &mut Engine, row: &Row) -> Result<(), EngineError>;
+
+    fn dispute(&self, _: &mut Engine, _: &Reference) -> Result<(), EngineError> {
+        Err(EngineError::InvalidTransactionType)
+    }
+
+    fn resolve(&self, _: &mut Engine, _: &Reference) -> Result<(), EngineError> {
+        Err(EngineError::InvalidTransactionType)
+    }
+
+    fn revert(&self, _: &mut Engine, _: &Reference) -> Result<(), EngineError> {
+        Err(EngineError::InvalidTransactionType)
+    }
+}
+
+/// Plain functions handle types that are neither stored nor disputable
+impl<F> TransactionHandler for F
+where
+    F: Fn(&mut Engine, &Row) -> Result<(), EngineError>,
+{
+    fn execute(&self, e: &mut Engine, row: &Row) -> Result<(), EngineError> {
+        self(e, row)
+    }
+}
+
+/// Plain function handling an admin type
+pub struct Admin<F>(pub F);
+
+impl<F> TransactionHandler for Admin<F>
+where
+    F: Fn(&mut Engine, &Row) -> Result<(), EngineError>,
+{
+    fn is_admin(&self) -> bool {
+        true
+    }
+
+    fn execute(&self, e: &mut Engine, row: &Row) -> Result<(), EngineError> {
+        (self.0)(e, row)
+    }
+}
+
+/// A dispute, resolve or chargeback row of `client` acting on its stored
+/// transaction `tx`, on all of what is left when there is no amount
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Reference {
+    pub client: ClientId,
+    pub tx: TransactionId,
+    pub amount: Option<Amount>,
+}
+
+impl Reference {
+    pub fn from_row(row: &Row) -> Result<Self, EngineError> {
+        match row.action {
+            Action::Dispute { amount }
+            | Action::Resolve { amount }
+            | Action::Chargeback { amount } => Ok(Self {
+                client: row.client,
+                tx: row.tx,
+                amount,
+            }),
+            _ => Err(EngineError::InvalidTransactionType),
+        }
+    }
+}
+
+/// Handler of every transaction type the engine knows about
+#[derive(Clone)]
+pub struct Handlers {
+    handlers: HashMap<TransactionType, Arc<dyn TransactionHandler + Send + Sync>>,
+}
+
+impl Default for Handlers {
+    fn default() -> Self {
+        let mut handlers = Self {
+            handlers: HashMap::new(),
+        };
+
+        handlers.register(TransactionType::DEPOSIT, deposit::Handler);
+        handlers.register(TransactionType::WITHDRAWAL, withdrawal::Handler);
+        handlers.register(TransactionType::TRANSFER, transfer::Handler);
+        handlers.register(TransactionType::AUTHORIZE, authorize::Handler);
+        handlers.register(TransactionType::DISPUTE, dispute::execute);
+        handlers.register(TransactionType::RESOLVE, resolve::execute);
+        handlers.register(TransactionType::CHARGEBACK, chargeback::execute);
+        handlers.register(TransactionType::UNLOCK, Admin(unlock::execute));
+        handlers.register(TransactionType::CAPTURE, capture::execute);
+        handlers.register(TransactionType::VOID, void::execute);
+        handlers.register(TransactionType::LIMIT, Admin(limits::execute));
+        handlers.register(TransactionType::CREDIT, Admin(credit::execute));
+        handlers.register(TransactionType::FREEZE, Admin(freeze::execute));
+        handlers.register(TransactionType::UNFREEZE, Admin(freeze::clear));
+
+        handlers
+    }
+}
+
+impl Handlers {
+    /// Adds a handler for a new type or replaces the one of a known type
+    pub fn register<H>(&mut self, kind: TransactionType, handler: H)
+    where
+        H: TransactionHandler + Send + Sync + 'static,
+    {
+        self.handlers.insert(kind, Arc::new(handler));
+    }
+
+    pub fn contains(&self, kind: TransactionType) -> bool {
+        self.handlers.contains_key(&kind)
+    }
+
+    pub fn get(
+        &self,
+        kind: TransactionType,
+    ) -> Result<Arc<dyn TransactionHandler + Send + Sync>, EngineError> {
+        self.handlers
+            .get(&kind)
+            .cloned()
+            .ok_or(EngineError::InvalidTransactionType)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Reference, TransactionHandler};
+    use crate::{
+        decimal::Decimal,
+        engine::{
+            testing::{amount_row, available, row},
+            Engine,
+        },
+        errors::EngineError,
+        row::{Action, Row},
+        transaction::{Transaction, TransactionType},
+    };
+
+    /// Credits points that can be clawed back by a dispute, using the public
+    /// engine API only
+    struct Loyalty;
+
+    impl TransactionHandler for Loyalty {
+        fn is_stored(&self) -> bool {
+            true
+        }
+
+        fn execute(&self, e: &mut Engine, row: &Row) -> Result<(), EngineError> {
+            let Action::Custom {
+                amount: Some(amount),
+                asset,
+                ..
+            } = row.action
+            else {
+                return Err(EngineError::InvalidTransactionType);
+            };
+
+            e.get_or_insert_client(row.client)
+                .active()?
+                .deposit_funds(asset, amount)?;
+            e.insert_transaction(Transaction::from_row(row).unwrap());
+            Ok(())
+        }
+
+        fn dispute(&self, e: &mut Engine, row: &Reference) -> Result<(), EngineError> {
+            let (target, client) = e.get_mut_transaction_client_pair(row.tx, row.client)?;
+            let amount = target.dispute_amount(row.amount)?;
+
+            client.active()?.hold_funds(target.asset, amount)?;
+            target.record_dispute(amount)?;
+            Ok(())
+        }
+    }
+
+    fn loyalty() -> TransactionType {
+        TransactionType::CUSTOM("loyalty".parse().unwrap())
+    }
+
+    #[test]
+    fn custom_transaction_type() {
+        let mut e = Engine::default();
+
+        assert!(!e.has_handler(loyalty()));
+        assert!(matches!(
+            e.execute(&row(loyalty(), 1, 1, 10)),
+            Err(EngineError::InvalidTransactionType)
+        ));
+
+        e.register_handler(loyalty(), Loyalty);
+        assert!(e.has_handler(loyalty()));
+        e.execute(&row(loyalty(), 1, 1, 10)).unwrap();
+        assert!(matches!(
+            e.execute(&row(TransactionType::DEPOSIT, 1, 1, 5)),
+            Err(EngineError::DuplicateTransaction(1))
+        ));
+        assert_eq!(available(&e, 1), Decimal::from(10));
+
+        e.execute(&row(TransactionType::DISPUTE, 1, 1, 4)).unwrap();
+        assert_eq!(available(&e, 1), Decimal::from(6));
+
+        // Nothing was registered to resolve loyalty credits
+        assert!(matches!(
+            e.execute(&row(TransactionType::RESOLVE, 1, 1, 0)),
+            Err(EngineError::InvalidTransactionType)
+        ));
+    }
+
+    #[test]
+    fn engine_is_send() {
+        fn assert_send<T: Send>(_: T) {}
+        assert_send(Engine::default().with_handler(loyalty(), Loyalty));
+    }
+
+    #[test]
+    fn replace_builtin_handler() {
+        let mut e = Engine::default();
+        let deposit = amount_row(TransactionType::DEPOSIT, 1, 1, Decimal::from(10));
+
+        e.register_handler(TransactionType::DEPOSIT, |_: &mut Engine, _: &Row| {
+            Err(EngineError::InvalidTransactionType)
+        });
+
+        assert!(e.execute(&deposit).is_err());
+        assert!(e.get_client(1).is_none());
+    }
+}
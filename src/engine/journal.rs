@@ -0,0 +1,264 @@
+/**
+ * Append-only log of every row the engine executed along with its outcome.
+ * Replaying the log into an engine set up with the same fees, limits and
+ * configuration rebuilds its state; each outcome is checked on the way, so a
+ * replay that doesn't reproduce the log stops with an error.
+ */
+use super::{Engine, LimitSetting};
+use crate::{
+    asset::Asset,
+    client::{ClientId, Freeze, Reason},
+    decimal::Amount,
+    errors::EngineError,
+    row::{RawRow, Row},
+    transaction::{Timestamp, TransactionId, TransactionType},
+};
+use serde::{Deserialize, Serialize};
+use std::{
+    error::Error,
+    fs::OpenOptions,
+    io::{Read, Write},
+    path::Path,
+};
+
+pub struct Journal {
+    writer: csv::Writer<Box<dyn Write + Send>>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct Entry {
+    #[serde(rename = "type")]
+    kind: TransactionType,
+    client: ClientId,
+    tx: TransactionId,
+    amount: Option<Amount>,
+    asset: Option<Asset>,
+    destination: Option<ClientId>,
+    reason: Option<Reason>,
+    timestamp: Option<Timestamp>,
+    expires: Option<Timestamp>,
+    limit: Option<LimitSetting>,
+    freeze: Option<Freeze>,
+    /// Whether the row came from the admin source
+    #[serde(default)]
+    admin: bool,
+    /// `EngineError::code` of why the row was rejected, empty when it was
+    /// applied
+    error: Option<String>,
+}
+
+impl Journal {
+    /// Entries are added to the end of the file, which is created if needed
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<Self, Box<dyn Error>> {
+        let file = OpenOptions::new().create(true).append(true).open(path)?;
+        let empty = file.metadata()?.len() == 0;
+
+        Ok(Self::new(Box::new(file), empty))
+    }
+
+    /// The header row is written along with the first entry when `headers`
+    /// is set
+    pub fn new(writer: Box<dyn Write + Send>, headers: bool) -> Self {
+        Self {
+            writer: csv::WriterBuilder::new()
+                .has_headers(headers)
+                .from_writer(writer),
+        }
+    }
+
+    /// Entries are flushed right away, a crash loses at most the last one
+    pub fn append(
+        &mut self,
+        row: &Row,
+        admin: bool,
+        result: &Result<(), EngineError>,
+    ) -> Result<(), Box<dyn Error>> {
+        let raw = RawRow::from(*row);
+        self.writer.serialize(Entry {
+            kind: raw.kind,
+            client: raw.client,
+            tx: raw.tx,
+            amount: raw.amount,
+            asset: raw.asset,
+            destination: raw.destination,
+            reason: raw.reason,
+            timestamp: raw.timestamp,
+            expires: raw.expires,
+            limit: raw.limit,
+            freeze: raw.freeze,
+            admin,
+            error: result.as_ref().err().map(|e| e.code().to_string()),
+        })?;
+        self.writer.flush()?;
+
+        Ok(())
+    }
+}
+
+impl From<&Entry> for RawRow {
+    fn from(entry: &Entry) -> Self {
+        RawRow {
+            kind: entry.kind,
+            client: entry.client,
+            tx: entry.tx,
+            amount: entry.amount,
+            asset: entry.asset,
+            destination: entry.destination,
+            reason: entry.reason,
+            timestamp: entry.timestamp,
+            expires: entry.expires,
+            limit: entry.limit,
+            freeze: entry.freeze,
+        }
+    }
+}
+
+/// Executes the logged rows in order, stopping after the first one with id
+/// `until` if given
+pub fn replay<R: Read>(
+    e: &mut Engine,
+    reader: R,
+    until: Option<TransactionId>,
+) -> Result<(), Box<dyn Error>> {
+    for entry in csv::Reader::from_reader(reader).deserialize() {
+        let entry: Entry = entry?;
+        let row = Row::try_from(RawRow::from(&entry))?;
+        let result = if entry.admin {
+            e.execute_admin(&row)
+        } else {
+            e.execute(&row)
+        };
+
+        if result.as_ref().err().map(EngineError::code) != entry.error.as_deref() {
+            return Err(EngineError::ReplayDiverged(entry.tx).into());
+        }
+        if until == Some(entry.tx) {
+            break;
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Journal;
+    use crate::{
+        asset::Asset,
+        client::Funds,
+        decimal::Decimal,
+        engine::{testing::row, Engine, LimitSchedule, LimitSetting},
+        errors::EngineError,
+        transaction::TransactionType,
+    };
+    use std::{
+        env::temp_dir,
+        fs,
+        io::{self, Write},
+        path::PathBuf,
+    };
+
+    /// A journal file on a full disk
+    /// Takes this many writes, then fails every one
+    struct Full(usize);
+
+    impl Write for Full {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            if self.0 == 0 {
+                return Err(io::Error::other("disk full"));
+            }
+            self.0 -= 1;
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    /// Runs a few rows, one of them failing, through a journaled engine
+    fn journaled(name: &str) -> (PathBuf, Funds) {
+        let path = temp_dir().join(format!("{}-{}.csv", name, std::process::id()));
+        let _ = fs::remove_file(&path);
+        let mut e = Engine::default().with_journal(Journal::open(&path).unwrap());
+
+        e.execute(&row(TransactionType::DEPOSIT, 1, 1, 100))
+            .unwrap();
+        e.execute(&row(TransactionType::WITHDRAWAL, 1, 2, 30))
+            .unwrap();
+        assert!(e
+            .execute(&row(TransactionType::WITHDRAWAL, 1, 3, 500))
+            .is_err());
+        e.execute_admin(
+            &row(TransactionType::LIMIT, 1, 4, 0)
+                .with_limit(LimitSetting::MaxWithdrawal(Some(Decimal::from(10)))),
+        )
+        .unwrap();
+        e.execute(&row(TransactionType::DISPUTE, 1, 1, 0)).unwrap();
+
+        (path, e.get_client(1).unwrap().get_funds(Asset::default()))
+    }
+
+    fn funds(e: &Engine) -> Funds {
+        e.get_client(1).unwrap().get_funds(Asset::default())
+    }
+
+    #[test]
+    fn replay_rebuilds_state() {
+        let (path, expected) = journaled("journal-replay");
+
+        let mut e = Engine::default();
+        e.replay(fs::File::open(&path).unwrap(), None).unwrap();
+        assert_eq!(funds(&e), expected);
+        assert_eq!(e.get_transaction(1).unwrap().amount, Decimal::from(100));
+
+        let mut e = Engine::default();
+        e.replay(fs::File::open(&path).unwrap(), Some(2)).unwrap();
+        assert_eq!(
+            funds(&e),
+            Funds {
+                available: Decimal::from(70),
+                held: Decimal::zero(),
+            }
+        );
+
+        fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn replay_checks_outcomes() {
+        let (path, _) = journaled("journal-diverged");
+        let mut limits = LimitSchedule::default();
+        limits.set(None, LimitSetting::MaxWithdrawal(Some(Decimal::from(20))));
+
+        let mut e = Engine::default().with_limits(limits);
+        let error = e.replay(fs::File::open(&path).unwrap(), None).unwrap_err();
+        assert_eq!(
+            error.downcast_ref::<EngineError>(),
+            Some(&EngineError::ReplayDiverged(2))
+        );
+
+        fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn append_failure_stops_processing() {
+        let mut e = Engine::default().with_journal(Journal::new(Box::new(Full(1)), true));
+        e.execute(&row(TransactionType::DEPOSIT, 1, 1, 100))
+            .unwrap();
+
+        // The withdrawal is rolled back since the journal couldn't record it
+        assert_eq!(
+            e.execute(&row(TransactionType::WITHDRAWAL, 1, 2, 30)),
+            Err(EngineError::JournalFailed)
+        );
+        assert_eq!(e.journal_failure().map(|f| f.tx), Some(2));
+        assert_eq!(funds(&e).available, Decimal::from(100));
+
+        assert_eq!(
+            e.execute(&row(TransactionType::DEPOSIT, 1, 3, 30)),
+            Err(EngineError::JournalFailed)
+        );
+        assert_eq!(funds(&e).available, Decimal::from(100));
+    }
+}
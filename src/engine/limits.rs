@@ -0,0 +1,574 @@
+/**
+ * Per-client limits on withdrawals and deposits. A limit set for a client
+ * overrides the default one of the same kind. Windowed limits look at the
+ * client's recent movements of the same asset, either the last N of them or
+ * the ones within a number of seconds of the engine clock. Movements are
+ * only kept as long as the client's longest window needs them, so a window
+ * that is widened later starts out with what the previous one kept.
+ */
+use super::{Authorization, Engine};
+use crate::{
+    asset::Asset,
+    client::ClientId,
+    decimal::Amount,
+    errors::EngineError,
+    row::{Action, Row},
+    transaction::Timestamp,
+};
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::{HashMap, VecDeque},
+    error::Error,
+    fmt::Display,
+    io::Read,
+    mem::discriminant,
+    str::FromStr,
+};
+
+/// Span of a rolling limit, the transaction being checked included
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Window {
+    /// The last N movements, written `10tx`
+    Transactions(u32),
+    /// Movements of the last N seconds, written `3600s`
+    Seconds(u64),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Limits {
+    pub max_withdrawal: Option<Amount>,
+    pub withdrawal_total: Option<(Amount, Window)>,
+    pub max_balance: Option<Amount>,
+    pub max_deposits: Option<(u32, Window)>,
+}
+
+/**
+ * A single limit as written in the limits file and in admin rows:
+ * `withdrawal=500`, `withdrawal_total=1000/10tx`, `balance=10000`,
+ * `deposits=5/86400s`. An empty value (`balance=`) removes the limit.
+ */
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(try_from = "String", into = "String")]
+pub enum LimitSetting {
+    MaxWithdrawal(Option<Amount>),
+    WithdrawalTotal(Option<(Amount, Window)>),
+    MaxBalance(Option<Amount>),
+    MaxDeposits(Option<(u32, Window)>),
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct LimitSchedule {
+    default: Limits,
+    clients: HashMap<ClientId, Vec<LimitSetting>>,
+}
+
+/// Funds of one asset that entered or left an account, kept for the
+/// windowed limits
+#[derive(Debug, Clone, Copy)]
+pub struct Movement {
+    pub amount: Amount,
+    pub credit: bool,
+    pub at: Option<Timestamp>,
+}
+
+#[derive(Deserialize)]
+struct LimitRow {
+    #[serde(default)]
+    client: Option<ClientId>,
+    limit: LimitSetting,
+}
+
+impl FromStr for Window {
+    type Err = EngineError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let window = if let Some(n) = s.strip_suffix("tx") {
+            n.parse().map(Window::Transactions)
+        } else if let Some(n) = s.strip_suffix('s') {
+            n.parse().map(Window::Seconds)
+        } else {
+            return Err(EngineError::InvalidLimit);
+        };
+
+        match window {
+            Ok(Window::Transactions(0)) | Ok(Window::Seconds(0)) | Err(_) => {
+                Err(EngineError::InvalidLimit)
+            }
+            Ok(window) => Ok(window),
+        }
+    }
+}
+
+impl FromStr for LimitSetting {
+    type Err = EngineError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (name, value) = s.split_once('=').ok_or(EngineError::InvalidLimit)?;
+
+        match name {
+            "withdrawal" => parse_value(value).map(LimitSetting::MaxWithdrawal),
+            "withdrawal_total" => parse_windowed(value).map(LimitSetting::WithdrawalTotal),
+            "balance" => parse_value(value).map(LimitSetting::MaxBalance),
+            "deposits" => parse_windowed(value).map(LimitSetting::MaxDeposits),
+            _ => Err(EngineError::InvalidLimit),
+        }
+    }
+}
+
+impl TryFrom<String> for LimitSetting {
+    type Error = EngineError;
+
+    fn try_from(s: String) -> Result<Self, Self::Error> {
+        s.parse()
+    }
+}
+
+impl From<LimitSetting> for String {
+    fn from(setting: LimitSetting) -> Self {
+        setting.to_string()
+    }
+}
+
+impl Display for Window {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Window::Transactions(n) => write!(f, "{}tx", n),
+            Window::Seconds(n) => write!(f, "{}s", n),
+        }
+    }
+}
+
+/// Written the way `FromStr` reads it
+impl Display for LimitSetting {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let (name, value) = match *self {
+            LimitSetting::MaxWithdrawal(max) => ("withdrawal", max.map(|m| m.to_string())),
+            LimitSetting::WithdrawalTotal(max) => (
+                "withdrawal_total",
+                max.map(|(m, window)| format!("{}/{}", m, window)),
+            ),
+            LimitSetting::MaxBalance(max) => ("balance", max.map(|m| m.to_string())),
+            LimitSetting::MaxDeposits(max) => (
+                "deposits",
+                max.map(|(m, window)| format!("{}/{}", m, window)),
+            ),
+        };
+
+        write!(f, "{}={}", name, value.unwrap_or_default())
+    }
+}
+
+fn parse_value<T: FromStr>(value: &str) -> Result<Option<T>, EngineError> {
+    if value.is_empty() {
+        return Ok(None);
+    }
+
+    value
+        .parse()
+        .map(Some)
+        .map_err(|_| EngineError::InvalidLimit)
+}
+
+fn parse_windowed<T: FromStr>(value: &str) -> Result<Option<(T, Window)>, EngineError> {
+    if value.is_empty() {
+        return Ok(None);
+    }
+
+    let (limit, window) = value.split_once('/').ok_or(EngineError::InvalidLimit)?;
+    let limit = limit.parse().map_err(|_| EngineError::InvalidLimit)?;
+
+    Ok(Some((limit, window.parse()?)))
+}
+
+impl Window {
+    /// Whether a movement at `at`, followed by `after` newer ones, can still
+    /// count towards the window of a transaction at `now`
+    fn covers(&self, at: Option<Timestamp>, after: usize, now: Option<Timestamp>) -> bool {
+        match *self {
+            Window::Transactions(n) => after + 1 < n as usize,
+            Window::Seconds(seconds) => at.unwrap_or(0).saturating_add(seconds) > now.unwrap_or(0),
+        }
+    }
+}
+
+impl Limits {
+    fn windows(&self) -> impl Iterator<Item = Window> {
+        [
+            self.withdrawal_total.map(|(_, window)| window),
+            self.max_deposits.map(|(_, window)| window),
+        ]
+        .into_iter()
+        .flatten()
+    }
+
+    pub fn apply(&mut self, setting: LimitSetting) {
+        match setting {
+            LimitSetting::MaxWithdrawal(max) => self.max_withdrawal = max,
+            LimitSetting::WithdrawalTotal(max) => self.withdrawal_total = max,
+            LimitSetting::MaxBalance(max) => self.max_balance = max,
+            LimitSetting::MaxDeposits(max) => self.max_deposits = max,
+        }
+    }
+}
+
+impl LimitSchedule {
+    /// Without a client the limit becomes the default for everyone
+    pub fn set(&mut self, client: Option<ClientId>, setting: LimitSetting) {
+        match client {
+            Some(client) => {
+                let settings = self.clients.entry(client).or_default();
+                settings.retain(|s| discriminant(s) != discriminant(&setting));
+                settings.push(setting);
+            }
+            None => self.default.apply(setting),
+        }
+    }
+
+    /// Settings of every client that overrides the defaults
+    pub fn client_settings(&self) -> impl Iterator<Item = (ClientId, LimitSetting)> + '_ {
+        self.clients
+            .iter()
+            .flat_map(|(client, settings)| settings.iter().map(|s| (*client, *s)))
+    }
+
+    pub fn limits_for(&self, client: ClientId) -> Limits {
+        let mut limits = self.default;
+        for setting in self.clients.get(&client).into_iter().flatten() {
+            limits.apply(*setting);
+        }
+
+        limits
+    }
+
+    /// Reads `client,limit` rows, rows without a client set the defaults
+    pub fn load<R: Read>(&mut self, reader: R) -> Result<(), Box<dyn Error>> {
+        for row in csv::Reader::from_reader(reader).deserialize() {
+            let row: LimitRow = row?;
+            self.set(row.client, row.limit);
+        }
+
+        Ok(())
+    }
+}
+
+/// Admin row changing one limit of a client
+pub fn execute(e: &mut Engine, row: &Row) -> Result<(), EngineError> {
+    let Action::Limit { setting } = row.action else {
+        return Err(EngineError::InvalidTransactionType);
+    };
+
+    e.limits.set(Some(row.client), setting);
+    Ok(())
+}
+
+/// Has to pass before `amount` leaves the account of `client`
+pub fn check_withdrawal(
+    e: &Engine,
+    client: ClientId,
+    asset: Asset,
+    amount: Amount,
+) -> Result<(), EngineError> {
+    let limits = e.limits.limits_for(client);
+
+    if let Some(max) = limits.max_withdrawal {
+        if amount > max {
+            return Err(EngineError::WithdrawalLimitExceeded(client, max));
+        }
+    }
+
+    if let Some((max, window)) = limits.withdrawal_total {
+        let total = recent(e, client, asset, window)
+            .filter(|m| !m.credit)
+            .try_fold(amount, |total, m| total.checked_add(m.amount))
+            .map_err(|_| EngineError::Overflow(client))?;
+        if total > max {
+            return Err(EngineError::WithdrawalTotalLimitExceeded(client, max));
+        }
+    }
+
+    Ok(())
+}
+
+/// Has to pass before `amount` enters the account of `client`
+pub fn check_deposit(
+    e: &Engine,
+    client: ClientId,
+    asset: Asset,
+    amount: Amount,
+) -> Result<(), EngineError> {
+    let limits = e.limits.limits_for(client);
+
+    if let Some(max) = limits.max_balance {
+        let available = e
+            .get_client(client)
+            .map_or(Amount::zero(), |c| c.get_funds(asset).available);
+        let balance = available
+            .checked_add(amount)
+            .map_err(|_| EngineError::Overflow(client))?;
+        if balance > max {
+            return Err(EngineError::BalanceLimitExceeded(client, max));
+        }
+    }
+
+    if let Some((max, window)) = limits.max_deposits {
+        let count = recent(e, client, asset, window)
+            .filter(|m| m.credit)
+            .count()
+            + 1;
+        if count > max as usize {
+            return Err(EngineError::DepositCountLimitExceeded(client, max));
+        }
+    }
+
+    Ok(())
+}
+
+/// Remembers the movements of an accepted transaction. A capture takes its
+/// asset, and without an amount everything that was open, from the
+/// authorization as it was before the row.
+pub fn record(e: &mut Engine, row: &Row, authorization: Option<Authorization>) {
+    let (amount, asset, debit, credit) = match (row.action, authorization) {
+        (Action::Deposit { amount, asset }, _) => (amount, asset, None, Some(row.client)),
+        (Action::Withdrawal { amount, asset }, _) => (amount, asset, Some(row.client), None),
+        (
+            Action::Transfer {
+                amount,
+                asset,
+                destination,
+            },
+            _,
+        ) => (amount, asset, Some(row.client), Some(destination)),
+        (Action::Capture { amount }, Some(authorization)) => (
+            amount.unwrap_or(authorization.amount),
+            authorization.asset,
+            Some(row.client),
+            None,
+        ),
+        _ => return,
+    };
+
+    for (client, is_credit) in [(debit, false), (credit, true)] {
+        if let Some(client) = client {
+            push(e, client, asset, amount, is_credit);
+        }
+    }
+}
+
+fn push(e: &mut Engine, client: ClientId, asset: Asset, amount: Amount, credit: bool) {
+    let history = e.movements.entry((client, asset)).or_default();
+    history.push_back(Movement {
+        amount,
+        credit,
+        at: e.now,
+    });
+
+    // Drop what no window of the client reaches anymore
+    let windows: Vec<Window> = e.limits.limits_for(client).windows().collect();
+    while let Some(oldest) = history.front() {
+        let after = history.len() - 1;
+        if windows.iter().any(|w| w.covers(oldest.at, after, e.now)) {
+            break;
+        }
+        history.pop_front();
+    }
+    if history.is_empty() {
+        e.movements.remove(&(client, asset));
+    }
+}
+
+/// Movements of `asset` within `window`, the one being checked excluded
+fn recent(
+    e: &Engine,
+    client: ClientId,
+    asset: Asset,
+    window: Window,
+) -> impl Iterator<Item = &Movement> {
+    let history = e.movements.get(&(client, asset));
+    let len = history.map_or(0, VecDeque::len);
+
+    history
+        .into_iter()
+        .flatten()
+        .enumerate()
+        .skip_while(move |(i, m)| !window.covers(m.at, len - 1 - i, e.now))
+        .map(|(_, m)| m)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{LimitSchedule, LimitSetting, Window};
+    use crate::{
+        asset::Asset,
+        decimal::Decimal,
+        engine::{testing::row, Engine},
+        errors::EngineError,
+        transaction::TransactionType,
+    };
+
+    fn schedule() -> LimitSchedule {
+        let mut limits = LimitSchedule::default();
+        limits
+            .load(
+                "client,limit\n\
+                 ,withdrawal=100\n\
+                 ,withdrawal_total=150/3tx\n\
+                 2,withdrawal=\n\
+                 2,balance=1000\n\
+                 3,deposits=2/60s\n"
+                    .as_bytes(),
+            )
+            .unwrap();
+        limits
+    }
+
+    #[test]
+    fn parse_settings() {
+        assert_eq!(
+            "withdrawal_total=1000/10tx"
+                .parse::<LimitSetting>()
+                .unwrap(),
+            LimitSetting::WithdrawalTotal(Some((Decimal::from(1000), Window::Transactions(10))))
+        );
+        assert_eq!(
+            "deposits=5/3600s".parse::<LimitSetting>().unwrap(),
+            LimitSetting::MaxDeposits(Some((5, Window::Seconds(3600))))
+        );
+        assert_eq!(
+            "balance=".parse::<LimitSetting>().unwrap(),
+            LimitSetting::MaxBalance(None)
+        );
+        assert!("deposits=5".parse::<LimitSetting>().is_err());
+        assert!("deposits=5/0tx".parse::<LimitSetting>().is_err());
+        assert!("overdraft=5".parse::<LimitSetting>().is_err());
+    }
+
+    #[test]
+    fn format_settings() {
+        for setting in [
+            "withdrawal=500.0",
+            "withdrawal_total=1000.5/10tx",
+            "balance=",
+            "deposits=5/3600s",
+        ] {
+            assert_eq!(
+                setting.parse::<LimitSetting>().unwrap().to_string(),
+                setting
+            );
+        }
+    }
+
+    #[test]
+    fn withdrawal_limits() {
+        let mut e = Engine::default().with_limits(schedule());
+        e.execute(&row(TransactionType::DEPOSIT, 1, 1, 1000))
+            .unwrap();
+
+        assert!(matches!(
+            e.execute(&row(TransactionType::WITHDRAWAL, 1, 2, 101)),
+            Err(EngineError::WithdrawalLimitExceeded(1, _))
+        ));
+        e.execute(&row(TransactionType::WITHDRAWAL, 1, 3, 100))
+            .unwrap();
+        assert!(matches!(
+            e.execute(&row(TransactionType::WITHDRAWAL, 1, 4, 60)),
+            Err(EngineError::WithdrawalTotalLimitExceeded(1, _))
+        ));
+        e.execute(&row(TransactionType::WITHDRAWAL, 1, 5, 50))
+            .unwrap();
+        e.execute(&row(TransactionType::DEPOSIT, 1, 6, 10)).unwrap();
+
+        // The first withdrawal fell out of the last 3 movements
+        e.execute(&row(TransactionType::WITHDRAWAL, 1, 7, 100))
+            .unwrap();
+        assert_eq!(e.movements[&(1, Asset::default())].len(), 2);
+    }
+
+    #[test]
+    fn client_overrides() {
+        let mut e = Engine::default().with_limits(schedule());
+
+        assert!(matches!(
+            e.execute(&row(TransactionType::DEPOSIT, 2, 1, 1001)),
+            Err(EngineError::BalanceLimitExceeded(2, _))
+        ));
+        e.execute(&row(TransactionType::DEPOSIT, 2, 2, 1000))
+            .unwrap();
+        // Client 2 has no maximum single withdrawal
+        e.execute(&row(TransactionType::WITHDRAWAL, 2, 3, 150))
+            .unwrap();
+    }
+
+    #[test]
+    fn deposits_per_time_window() {
+        let mut e = Engine::default().with_limits(schedule());
+
+        for (tx, timestamp) in [(1, 0), (2, 30)] {
+            e.execute(&row(TransactionType::DEPOSIT, 3, tx, 1).with_timestamp(timestamp))
+                .unwrap();
+        }
+        assert!(matches!(
+            e.execute(&row(TransactionType::DEPOSIT, 3, 3, 1).with_timestamp(59)),
+            Err(EngineError::DepositCountLimitExceeded(3, 2))
+        ));
+        e.execute(&row(TransactionType::DEPOSIT, 3, 4, 1).with_timestamp(60))
+            .unwrap();
+        // Withdrawals look at 3 movements, deposits at a minute
+        let history = &e.movements[&(3, Asset::default())];
+        assert_eq!(history.len(), 2);
+        assert_eq!(history[0].at, Some(30));
+    }
+
+    #[test]
+    fn admin_rows_change_limits() {
+        let mut e = Engine::default().with_limits(schedule());
+        e.execute(&row(TransactionType::DEPOSIT, 1, 1, 1000))
+            .unwrap();
+
+        e.execute_admin(
+            &row(TransactionType::LIMIT, 1, 2, 0).with_limit("withdrawal=500".parse().unwrap()),
+        )
+        .unwrap();
+        e.execute(&row(TransactionType::WITHDRAWAL, 1, 3, 120))
+            .unwrap();
+        assert!(matches!(
+            e.execute(&row(TransactionType::WITHDRAWAL, 1, 4, 501)),
+            Err(EngineError::WithdrawalLimitExceeded(1, _))
+        ));
+    }
+
+    #[test]
+    fn empty_window_keeps_nothing() {
+        let mut limits = LimitSchedule::default();
+        limits.set(
+            None,
+            LimitSetting::WithdrawalTotal(Some((Decimal::from(100), Window::Transactions(0)))),
+        );
+        let mut e = Engine::default().with_limits(limits);
+
+        e.execute(&row(TransactionType::DEPOSIT, 1, 1, 10)).unwrap();
+        assert!(!e.movements.contains_key(&(1, Asset::default())));
+    }
+
+    #[test]
+    fn captures_are_withdrawals() {
+        let mut e = Engine::default().with_limits(schedule());
+        e.execute(&row(TransactionType::DEPOSIT, 1, 1, 1000))
+            .unwrap();
+        e.execute(&row(TransactionType::AUTHORIZE, 1, 2, 300))
+            .unwrap();
+
+        assert!(matches!(
+            e.execute(&row(TransactionType::CAPTURE, 1, 2, 0)),
+            Err(EngineError::WithdrawalLimitExceeded(1, _))
+        ));
+        e.execute(&row(TransactionType::CAPTURE, 1, 2, 100))
+            .unwrap();
+        e.execute(&row(TransactionType::WITHDRAWAL, 1, 3, 50))
+            .unwrap();
+        // The capture counts toward the rolling total
+        assert!(matches!(
+            e.execute(&row(TransactionType::CAPTURE, 1, 2, 1)),
+            Err(EngineError::WithdrawalTotalLimitExceeded(1, _))
+        ));
+    }
+}
@@ -0,0 +1,439 @@
+/**
+ * Versioned on-disk copy of the engine state, so that a run can pick up where
+ * the previous one stopped. It is a headerless CSV file whose first field
+ * names the record: `snapshot,<version>` comes first, followed by the
+ * fingerprint of the configuration it was taken under, the clock,
+ * the accounts with their balances, receivables and freezes, the stored
+ * transactions with their dispute state, the ids of rejected rows, open
+ * authorizations, lock history, per-client limits and the movements the
+ * windowed limits look at.
+ *
+ * Fees, default limits and dispute rules are configuration and aren't saved.
+ * Dispute rules and the negative balance policy decide what the saved dispute
+ * state means though, so a snapshot is only restored under the same ones.
+ */
+use super::{limits::Movement, Authorization, Engine, LimitSetting, LockChange};
+use crate::{
+    asset::Asset,
+    client::{Client, ClientId, Freeze, Funds, Reason},
+    decimal::Amount,
+    errors::EngineError,
+    transaction::{
+        DisputedAmounts, Timestamp, Transaction, TransactionDisputeStatus, TransactionId,
+        TransactionType,
+    },
+};
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::{HashMap, HashSet, VecDeque},
+    error::Error,
+    io::{Read, Write},
+};
+
+pub const SNAPSHOT_VERSION: u32 = 2;
+
+#[derive(Serialize, Deserialize)]
+struct TransactionRecord<'a> {
+    record: &'a str,
+    kind: TransactionType,
+    client: ClientId,
+    tx: TransactionId,
+    amount: Amount,
+    asset: Asset,
+    destination: Option<ClientId>,
+    reason: Option<Reason>,
+    timestamp: Option<Timestamp>,
+    expires: Option<Timestamp>,
+    fee: Amount,
+    status: TransactionDisputeStatus,
+    disputed: Amount,
+    resolved: Amount,
+    charged_back: Amount,
+    shortfall: Amount,
+    receivable: Amount,
+}
+
+/// Records come out ordered by client and transaction id
+pub fn write<W: Write>(e: &Engine, writer: W) -> Result<(), Box<dyn Error>> {
+    let mut wtr = csv::WriterBuilder::new()
+        .has_headers(false)
+        .flexible(true)
+        .from_writer(writer);
+    let sorted = |mut ids: Vec<u32>| {
+        ids.sort();
+        ids
+    };
+
+    wtr.serialize(("snapshot", SNAPSHOT_VERSION))?;
+    wtr.serialize(("config", e.config.fingerprint()))?;
+    if let Some(now) = e.now {
+        wtr.serialize(("now", now))?;
+    }
+
+    let mut clients: Vec<&Client> = e.clients.values().collect();
+    clients.sort_by_key(|c| c.id());
+    for client in clients {
+        let id = client.id();
+        wtr.serialize(("client", id, client.credit_limit()))?;
+        for (asset, funds) in client.iter_funds() {
+            wtr.serialize(("funds", id, asset, funds.available, funds.held))?;
+        }
+        for (asset, receivable) in client.iter_receivables() {
+            wtr.serialize(("receivable", id, asset, receivable))?;
+        }
+        for (freeze, reason) in client.freezes() {
+            wtr.serialize(("freeze", id, freeze, reason))?;
+        }
+        for change in e.lock_history(id) {
+            wtr.serialize(("lock", id, change.tx, change.locked, change.reason))?;
+        }
+        let mut assets: Vec<Asset> = e
+            .movements
+            .keys()
+            .filter(|(client, _)| *client == id)
+            .map(|(_, asset)| *asset)
+            .collect();
+        assets.sort();
+        for (asset, movement) in assets
+            .into_iter()
+            .flat_map(|asset| e.movements[&(id, asset)].iter().map(move |m| (asset, m)))
+        {
+            wtr.serialize((
+                "movement",
+                id,
+                asset,
+                movement.amount,
+                movement.credit,
+                movement.at,
+            ))?;
+        }
+    }
+
+    for tx in sorted(e.transactions.keys().copied().collect()) {
+        let t = &e.transactions[&tx];
+        let d = t.disputed_amounts;
+        wtr.serialize(TransactionRecord {
+            record: "transaction",
+            kind: t.kind,
+            client: t.client,
+            tx: t.tx,
+            amount: t.amount,
+            asset: t.asset,
+            destination: t.destination,
+            reason: t.reason,
+            timestamp: t.timestamp,
+            expires: t.expires,
+            fee: t.fee,
+            status: t.dispute_status,
+            disputed: d.disputed,
+            resolved: d.resolved,
+            charged_back: d.charged_back,
+            shortfall: d.shortfall,
+            receivable: d.receivable,
+        })?;
+    }
+
+    for tx in sorted(e.rejected.iter().copied().collect()) {
+        wtr.serialize(("rejected", tx))?;
+    }
+
+    for tx in sorted(e.authorizations.keys().copied().collect()) {
+        let a = &e.authorizations[&tx];
+        wtr.serialize(("authorization", tx, a.client, a.asset, a.amount, a.expires))?;
+    }
+
+    let mut settings: Vec<(ClientId, LimitSetting)> = e.limits.client_settings().collect();
+    settings.sort_by_key(|(client, _)| *client);
+    for (client, setting) in settings {
+        wtr.serialize(("limit", client, setting))?;
+    }
+
+    wtr.flush()?;
+    Ok(())
+}
+
+/// Replaces the state of `e`, which is left untouched if the snapshot can't
+/// be read
+pub fn read<R: Read>(e: &mut Engine, reader: R) -> Result<(), Box<dyn Error>> {
+    let mut rdr = csv::ReaderBuilder::new()
+        .has_headers(false)
+        .flexible(true)
+        .from_reader(reader);
+    let mut records = rdr.records();
+
+    let header = match records.next() {
+        Some(record) => record?,
+        None => return Err(EngineError::DeserializationError("empty snapshot").into()),
+    };
+    if header.deserialize::<(&str, u32)>(None)? != ("snapshot", SNAPSHOT_VERSION) {
+        return Err(EngineError::DeserializationError("unsupported snapshot version").into());
+    }
+
+    let mut config = None;
+    let mut now = None;
+    let mut clients = HashMap::new();
+    let mut transactions = HashMap::new();
+    let mut rejected = HashSet::new();
+    let mut authorizations = HashMap::new();
+    let mut lock_history: HashMap<ClientId, Vec<LockChange>> = HashMap::new();
+    let mut movements: HashMap<(ClientId, Asset), VecDeque<Movement>> = HashMap::new();
+    let mut limits = Vec::new();
+
+    for record in records {
+        let record = record?;
+
+        match record.get(0) {
+            Some("config") => {
+                let (_, fingerprint): (&str, String) = record.deserialize(None)?;
+                config = Some(fingerprint);
+            }
+            Some("now") => {
+                let (_, at): (&str, Timestamp) = record.deserialize(None)?;
+                now = Some(at);
+            }
+            Some("client") => {
+                let (_, id, credit_limit): (&str, ClientId, Amount) = record.deserialize(None)?;
+                let mut c = Client::new(id);
+                c.set_credit_limit(credit_limit)?;
+                clients.insert(id, c);
+            }
+            Some("funds") => {
+                let (_, id, asset, available, held): (&str, ClientId, Asset, Amount, Amount) =
+                    record.deserialize(None)?;
+                clients
+                    .get_mut(&id)
+                    .ok_or(EngineError::ClientNotFound(id))?
+                    .restore_funds(asset, Funds { available, held });
+            }
+            Some("receivable") => {
+                let (_, id, asset, receivable): (&str, ClientId, Asset, Amount) =
+                    record.deserialize(None)?;
+                clients
+                    .get_mut(&id)
+                    .ok_or(EngineError::ClientNotFound(id))?
+                    .restore_receivable(asset, receivable);
+            }
+            Some("freeze") => {
+                let (_, id, freeze, reason): (&str, ClientId, Freeze, Reason) =
+                    record.deserialize(None)?;
+                clients
+                    .get_mut(&id)
+                    .ok_or(EngineError::ClientNotFound(id))?
+                    .freeze(freeze, reason);
+            }
+            Some("lock") => {
+                let (_, id, tx, locked, reason): (&str, ClientId, TransactionId, bool, _) =
+                    record.deserialize(None)?;
+                lock_history
+                    .entry(id)
+                    .or_default()
+                    .push(LockChange { tx, locked, reason });
+            }
+            Some("movement") => {
+                let (_, id, asset, amount, credit, at): (&str, ClientId, Asset, Amount, bool, _) =
+                    record.deserialize(None)?;
+                movements
+                    .entry((id, asset))
+                    .or_default()
+                    .push_back(Movement { amount, credit, at });
+            }
+            Some("transaction") => {
+                let r: TransactionRecord = record.deserialize(None)?;
+                let mut t = Transaction::new(r.kind, r.client, r.tx, r.amount).with_asset(r.asset);
+                t.destination = r.destination;
+                t.reason = r.reason;
+                t.timestamp = r.timestamp;
+                t.expires = r.expires;
+                t.fee = r.fee;
+                t.dispute_status = r.status;
+                t.disputed_amounts = DisputedAmounts {
+                    disputed: r.disputed,
+                    resolved: r.resolved,
+                    charged_back: r.charged_back,
+                    shortfall: r.shortfall,
+                    receivable: r.receivable,
+                };
+                transactions.insert(t.tx, t);
+            }
+            Some("rejected") => {
+                let (_, tx): (&str, TransactionId) = record.deserialize(None)?;
+                rejected.insert(tx);
+            }
+            Some("authorization") => {
+                let (_, tx, client, asset, amount, expires): (
+                    &str,
+                    TransactionId,
+                    ClientId,
+                    Asset,
+                    Amount,
+                    Option<Timestamp>,
+                ) = record.deserialize(None)?;
+                authorizations.insert(
+                    tx,
+                    Authorization {
+                        client,
+                        asset,
+                        amount,
+                        expires,
+                    },
+                );
+            }
+            Some("limit") => {
+                let (_, client, setting): (&str, ClientId, LimitSetting) =
+                    record.deserialize(None)?;
+                limits.push((client, setting));
+            }
+            _ => return Err(EngineError::DeserializationError("unknown snapshot record").into()),
+        }
+    }
+
+    if config != Some(e.config.fingerprint()) {
+        return Err(EngineError::DeserializationError(
+            "snapshot was taken under another configuration",
+        )
+        .into());
+    }
+
+    e.now = now;
+    e.clients = clients;
+    e.transactions = transactions;
+    e.rejected = rejected;
+    e.authorizations = authorizations;
+    e.lock_history = lock_history;
+    e.movements = movements;
+    for (client, setting) in limits {
+        e.limits.set(Some(client), setting);
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        client::Freeze,
+        decimal::Decimal,
+        engine::{
+            testing::{amount_row, funds, row},
+            ChargebackPolicy, DisputePolicy, DisputeRules, Engine, EngineConfig, LimitSetting,
+            NegativeBalancePolicy, ResolvePolicy,
+        },
+        errors::EngineError,
+        transaction::{TransactionDisputeStatus, TransactionType},
+    };
+
+    #[test]
+    fn restore_continues_where_snapshot_stopped() {
+        let mut e = Engine::default();
+
+        e.execute(&row(TransactionType::DEPOSIT, 1, 1, 100))
+            .unwrap();
+        e.execute(&row(TransactionType::DEPOSIT, 1, 2, 50)).unwrap();
+        e.execute(&row(TransactionType::DISPUTE, 1, 1, 30)).unwrap();
+        e.execute(&row(TransactionType::DEPOSIT, 2, 3, 10)).unwrap();
+        assert!(e
+            .execute(&row(TransactionType::WITHDRAWAL, 2, 8, 20))
+            .is_err());
+        e.execute(&row(TransactionType::DISPUTE, 2, 3, 0)).unwrap();
+        e.execute(&row(TransactionType::CHARGEBACK, 2, 3, 0))
+            .unwrap();
+        e.execute_admin(
+            &row(TransactionType::FREEZE, 1, 4, 0)
+                .with_freeze(Freeze::Credits)
+                .with_reason("aml".parse().unwrap()),
+        )
+        .unwrap();
+        e.execute_admin(
+            &row(TransactionType::LIMIT, 1, 5, 0)
+                .with_limit(LimitSetting::MaxWithdrawal(Some(Decimal::from(20)))),
+        )
+        .unwrap();
+
+        let mut saved = Vec::new();
+        e.snapshot(&mut saved).unwrap();
+        let mut restored = Engine::default();
+        restored.restore(saved.as_slice()).unwrap();
+
+        assert_eq!(funds(&restored, 1), funds(&e, 1));
+        assert!(restored.get_client(2).unwrap().is_locked());
+        assert_eq!(restored.lock_history(2), e.lock_history(2));
+        assert_eq!(
+            restored.get_transaction(1).unwrap().dispute_status,
+            TransactionDisputeStatus::DISPUTED
+        );
+        assert_eq!(
+            restored.get_transaction(1).unwrap().disputed_amounts,
+            e.get_transaction(1).unwrap().disputed_amounts
+        );
+
+        // Disputes, freezes and limits carry over
+        restored
+            .execute(&row(TransactionType::RESOLVE, 1, 1, 0))
+            .unwrap();
+        assert_eq!(funds(&restored, 1).held, Decimal::zero());
+        assert!(matches!(
+            restored.execute(&row(TransactionType::DEPOSIT, 1, 6, 1)),
+            Err(EngineError::AccountFrozen(1, Freeze::Credits, _))
+        ));
+        assert!(matches!(
+            restored.execute(&row(TransactionType::WITHDRAWAL, 1, 7, 25)),
+            Err(EngineError::WithdrawalLimitExceeded(1, _))
+        ));
+        assert!(matches!(
+            restored.execute(&row(TransactionType::DEPOSIT, 3, 2, 50)),
+            Err(EngineError::DuplicateTransaction(2))
+        ));
+        assert!(matches!(
+            restored.execute(&row(TransactionType::DEPOSIT, 2, 8, 20)),
+            Err(EngineError::DuplicateTransaction(8))
+        ));
+
+        let mut again = Vec::new();
+        e.snapshot(&mut again).unwrap();
+        assert_eq!(again, saved);
+    }
+
+    #[test]
+    fn unknown_version() {
+        let mut e = Engine::default();
+        e.execute(&amount_row(
+            TransactionType::DEPOSIT,
+            1,
+            1,
+            Decimal::from(10),
+        ))
+        .unwrap();
+
+        assert!(e.restore("snapshot,3\nclient,1,0\n".as_bytes()).is_err());
+        assert!(e.restore("client,1,0\n".as_bytes()).is_err());
+        assert_eq!(funds(&e, 1).available, Decimal::from(10));
+    }
+
+    #[test]
+    fn restore_requires_same_config() {
+        let mut e = Engine::default();
+        e.execute(&row(TransactionType::DEPOSIT, 1, 1, 10)).unwrap();
+        let mut saved = Vec::new();
+        e.snapshot(&mut saved).unwrap();
+
+        let capped =
+            EngineConfig::default().with_negative_balance_policy(NegativeBalancePolicy::Cap);
+        let unresolvable = EngineConfig::default().with_dispute_rules(
+            TransactionType::DEPOSIT,
+            DisputeRules {
+                dispute: DisputePolicy::Hold,
+                resolve: ResolvePolicy::Reject,
+                chargeback: ChargebackPolicy::Lock,
+            },
+        );
+        for config in [capped, unresolvable] {
+            let mut restored = Engine::new(config);
+            assert!(restored.restore(saved.as_slice()).is_err());
+            assert!(restored.get_client(1).is_none());
+        }
+
+        let mut restored = Engine::new(EngineConfig::default());
+        restored.restore(saved.as_slice()).unwrap();
+        assert_eq!(funds(&restored, 1), funds(&e, 1));
+    }
+}
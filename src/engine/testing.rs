@@ -0,0 +1,119 @@
+//! Shorthands shared by the engine tests
+use super::{Engine, LimitSetting};
+use crate::{
+    asset::Asset,
+    client::{ClientId, Freeze, Funds},
+    decimal::Amount,
+    row::{Action, Row},
+    transaction::{Timestamp, TransactionId, TransactionType},
+};
+
+pub fn row(kind: TransactionType, client: ClientId, tx: TransactionId, amount: i64) -> Row {
+    amount_row(kind, client, tx, Amount::from(amount))
+}
+
+/// Row of `kind` in the default asset. A zero amount on the types that
+/// reference another transaction means all of it; transfers go to `client`
+/// itself, limits and freezes get a placeholder until set with the builders.
+pub fn amount_row(
+    kind: TransactionType,
+    client: ClientId,
+    tx: TransactionId,
+    amount: Amount,
+) -> Row {
+    let asset = Asset::default();
+    let partial = (amount != Amount::zero()).then_some(amount);
+
+    let action = match kind {
+        TransactionType::DEPOSIT => Action::Deposit { amount, asset },
+        TransactionType::WITHDRAWAL => Action::Withdrawal { amount, asset },
+        TransactionType::TRANSFER => Action::Transfer {
+            amount,
+            asset,
+            destination: client,
+        },
+        TransactionType::AUTHORIZE => Action::Authorize {
+            amount,
+            asset,
+            expires: None,
+        },
+        TransactionType::DISPUTE => Action::Dispute { amount: partial },
+        TransactionType::RESOLVE => Action::Resolve { amount: partial },
+        TransactionType::CHARGEBACK => Action::Chargeback { amount: partial },
+        TransactionType::CAPTURE => Action::Capture { amount: partial },
+        TransactionType::VOID => Action::Void,
+        TransactionType::UNLOCK => Action::Unlock,
+        TransactionType::CREDIT => Action::Credit { amount },
+        TransactionType::LIMIT => Action::Limit {
+            setting: LimitSetting::MaxWithdrawal(None),
+        },
+        TransactionType::FREEZE => Action::Freeze {
+            freeze: Freeze::Debits,
+        },
+        TransactionType::UNFREEZE => Action::Unfreeze {
+            freeze: Freeze::Debits,
+        },
+        TransactionType::CUSTOM(kind) => Action::Custom {
+            kind,
+            amount: Some(amount),
+            asset,
+        },
+    };
+
+    Row::new(client, tx, action)
+}
+
+impl Row {
+    pub fn with_asset(mut self, asset: Asset) -> Self {
+        match &mut self.action {
+            Action::Deposit { asset: a, .. }
+            | Action::Withdrawal { asset: a, .. }
+            | Action::Transfer { asset: a, .. }
+            | Action::Authorize { asset: a, .. }
+            | Action::Custom { asset: a, .. } => *a = asset,
+            _ => panic!("{} rows have no asset", self.kind()),
+        }
+        self
+    }
+
+    pub fn with_destination(mut self, destination: ClientId) -> Self {
+        match &mut self.action {
+            Action::Transfer { destination: d, .. } => *d = destination,
+            _ => panic!("{} rows have no destination", self.kind()),
+        }
+        self
+    }
+
+    pub fn with_expiry(mut self, expires: Timestamp) -> Self {
+        match &mut self.action {
+            Action::Authorize { expires: x, .. } => *x = Some(expires),
+            _ => panic!("{} rows have no expiry", self.kind()),
+        }
+        self
+    }
+
+    pub fn with_limit(mut self, limit: LimitSetting) -> Self {
+        match &mut self.action {
+            Action::Limit { setting } => *setting = limit,
+            _ => panic!("{} rows have no limit", self.kind()),
+        }
+        self
+    }
+
+    pub fn with_freeze(mut self, freeze: Freeze) -> Self {
+        match &mut self.action {
+            Action::Freeze { freeze: f } | Action::Unfreeze { freeze: f } => *f = freeze,
+            _ => panic!("{} rows have no freeze", self.kind()),
+        }
+        self
+    }
+}
+
+/// Balances of `client` in the default asset
+pub fn funds(e: &Engine, client: ClientId) -> Funds {
+    e.get_client(client).unwrap().get_funds(Asset::default())
+}
+
+pub fn available(e: &Engine, client: ClientId) -> Amount {
+    funds(e, client).available
+}
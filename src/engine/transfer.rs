@@ -0,0 +1,227 @@
+use super::{fees, limits, DisputePolicy, DisputeRules, Engine, Reference, TransactionHandler};
+use crate::client::Client;
+use crate::decimal::Amount;
+use crate::errors::EngineError;
+use crate::row::{Action, Row};
+use crate::transaction::{Transaction, TransactionType};
+
+/**
+ * Moves funds from `client` to `destination`. Both accounts have to be
+ * unlocked, otherwise nothing moves. A destination without an account gets
+ * one, like on a deposit.
+ */
+pub fn execute(e: &mut Engine, row: &Row) -> Result<(), EngineError> {
+    let Action::Transfer {
+        amount,
+        asset,
+        destination,
+    } = row.action
+    else {
+        return Err(EngineError::InvalidTransactionType);
+    };
+    if destination == row.client {
+        return Err(EngineError::InvalidDestination(row.tx));
+    }
+    let fee = e.fees.fee_for(row.kind(), row.client, amount)?;
+    limits::check_withdrawal(e, row.client, asset, amount)?;
+    limits::check_deposit(e, destination, asset, amount)?;
+
+    e.clients
+        .get_mut(&row.client)
+        .ok_or(EngineError::ClientNotFound(row.client))?
+        .active()?
+        .withdraw_funds(asset, amount)?;
+    e.clients
+        .entry(destination)
+        .or_insert(Client::new(destination))
+        .active()?
+        .deposit_funds(asset, amount)?;
+    fees::charge(e, row.client, asset, fee)?;
+
+    if let Some(transaction) = Transaction::from_row(row) {
+        e.transactions
+            .insert(row.tx, Transaction { fee, ..transaction });
+    }
+    Ok(())
+}
+
+/// A hold is placed on the receiving side
+pub fn dispute(
+    _: &mut Client,
+    destination: &mut Client,
+    transaction: &mut Transaction,
+    requested: Option<Amount>,
+    rules: DisputeRules,
+) -> Result<(), EngineError> {
+    rules.dispute.check(transaction.kind)?;
+    let amount = transaction.dispute_amount(requested)?;
+
+    if rules.dispute == DisputePolicy::Hold {
+        destination
+            .active()?
+            .hold_funds(transaction.asset, amount)?;
+    }
+    transaction.record_dispute(amount)?;
+
+    Ok(())
+}
+
+pub fn resolve(
+    _: &mut Client,
+    destination: &mut Client,
+    transaction: &mut Transaction,
+    requested: Option<Amount>,
+    rules: DisputeRules,
+) -> Result<(), EngineError> {
+    rules.resolve.check(transaction.kind)?;
+    let amount = transaction.settle_amount(requested)?;
+
+    if rules.dispute == DisputePolicy::Hold {
+        destination.release_funds(transaction.asset, amount)?;
+    }
+    transaction.record_resolve(amount)?;
+
+    Ok(())
+}
+
+/// The funds go back to the sender. The chargeback policy applies to the
+/// sender as well: it raised the dispute, and must not be able to lock
+/// somebody else's account by sending it funds and charging them back.
+pub fn revert(
+    source: &mut Client,
+    destination: &mut Client,
+    transaction: &mut Transaction,
+    requested: Option<Amount>,
+    rules: DisputeRules,
+) -> Result<(), EngineError> {
+    let amount = transaction.settle_amount(requested)?;
+
+    match rules.dispute {
+        DisputePolicy::Hold => destination.discard_held_funds(transaction.asset, amount)?,
+        _ => destination.take_funds(transaction.asset, amount)?,
+    };
+    source.refund_funds(transaction.asset, amount)?;
+    rules.chargeback.apply(source)?;
+    transaction.record_chargeback(amount)?;
+
+    Ok(())
+}
+
+pub struct Handler;
+
+impl TransactionHandler for Handler {
+    fn is_stored(&self) -> bool {
+        true
+    }
+
+    fn execute(&self, e: &mut Engine, row: &Row) -> Result<(), EngineError> {
+        execute(e, row)
+    }
+
+    fn dispute(&self, e: &mut Engine, row: &Reference) -> Result<(), EngineError> {
+        let rules = e.config.dispute_rules(TransactionType::TRANSFER)?;
+        let (target, source, destination) = e.get_mut_transfer_parties(row.tx, row.client)?;
+
+        dispute(source, destination, target, row.amount, rules)
+    }
+
+    fn resolve(&self, e: &mut Engine, row: &Reference) -> Result<(), EngineError> {
+        let rules = e.config.dispute_rules(TransactionType::TRANSFER)?;
+        let (target, source, destination) = e.get_mut_transfer_parties(row.tx, row.client)?;
+
+        resolve(source, destination, target, row.amount, rules)
+    }
+
+    fn revert(&self, e: &mut Engine, row: &Reference) -> Result<(), EngineError> {
+        let rules = e.config.dispute_rules(TransactionType::TRANSFER)?;
+        let (target, source, destination) = e.get_mut_transfer_parties(row.tx, row.client)?;
+
+        revert(source, destination, target, row.amount, rules)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        decimal::Decimal,
+        engine::{
+            testing::{amount_row, available},
+            Engine,
+        },
+        errors::EngineError,
+        row::Row,
+        transaction::TransactionType,
+    };
+
+    fn setup() -> Engine {
+        let mut e = Engine::default();
+        e.execute(&amount_row(
+            TransactionType::DEPOSIT,
+            1,
+            1,
+            Decimal::from(100),
+        ))
+        .unwrap();
+        e
+    }
+
+    fn transfer(tx: u32, amount: i64) -> Row {
+        amount_row(TransactionType::TRANSFER, 1, tx, Decimal::from(amount)).with_destination(2)
+    }
+
+    #[test]
+    fn correct_execution() {
+        let mut e = setup();
+
+        e.execute(&transfer(2, 40)).unwrap();
+
+        assert_eq!(available(&e, 1), Decimal::from(60));
+        assert_eq!(available(&e, 2), Decimal::from(40));
+    }
+
+    #[test]
+    fn failed_transfer_changes_nothing() {
+        let mut e = setup();
+
+        assert!(matches!(
+            e.execute(&transfer(4, 400)),
+            Err(EngineError::InsufficientFunds(..))
+        ));
+        assert!(e.get_client(2).is_none());
+
+        e.execute(&transfer(2, 40)).unwrap();
+        e.execute(&amount_row(TransactionType::DISPUTE, 1, 2, Decimal::zero()))
+            .unwrap();
+        e.execute(&amount_row(
+            TransactionType::CHARGEBACK,
+            1,
+            2,
+            Decimal::zero(),
+        ))
+        .unwrap();
+
+        assert!(matches!(
+            e.execute(&transfer(3, 10)),
+            Err(EngineError::AccountLocked(1))
+        ));
+        assert_eq!(available(&e, 1), Decimal::from(100));
+        assert!(!e.get_client(2).unwrap().is_locked());
+    }
+
+    #[test]
+    fn dispute_and_resolve() {
+        let mut e = setup();
+
+        e.execute(&transfer(2, 40)).unwrap();
+        e.execute(&amount_row(TransactionType::DISPUTE, 1, 2, Decimal::zero()))
+            .unwrap();
+
+        assert_eq!(available(&e, 2), Decimal::zero());
+
+        e.execute(&amount_row(TransactionType::RESOLVE, 1, 2, Decimal::zero()))
+            .unwrap();
+
+        assert_eq!(available(&e, 2), Decimal::from(40));
+        assert!(!e.get_client(2).unwrap().is_locked());
+    }
+}
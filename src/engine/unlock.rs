@@ -0,0 +1,73 @@
+use super::Engine;
+use crate::errors::EngineError;
+use crate::row::Row;
+
+/// Administrative reactivation of a locked account, a reason is mandatory
+pub fn execute(e: &mut Engine, row: &Row) -> Result<(), EngineError> {
+    row.reason.ok_or(EngineError::MissingReason(row.tx))?;
+
+    e.clients
+        .get_mut(&row.client)
+        .ok_or(EngineError::ClientNotFound(row.client))?
+        .unlock()
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        decimal::Decimal,
+        engine::{testing::amount_row, Engine, LockChange},
+        errors::EngineError,
+        row::Row,
+        transaction::TransactionType,
+    };
+
+    fn row(kind: TransactionType, tx: u32) -> Row {
+        amount_row(kind, 1, tx, Decimal::zero())
+    }
+
+    #[test]
+    fn unlock_after_chargeback() {
+        let mut e = Engine::default();
+        let reason = "REVIEWED".parse().unwrap();
+
+        e.execute(&amount_row(
+            TransactionType::DEPOSIT,
+            1,
+            1,
+            Decimal::from(10),
+        ))
+        .unwrap();
+        e.execute(&row(TransactionType::DISPUTE, 1)).unwrap();
+        e.execute(&row(TransactionType::CHARGEBACK, 1)).unwrap();
+
+        assert!(matches!(
+            e.execute_admin(&row(TransactionType::UNLOCK, 2)),
+            Err(EngineError::MissingReason(2))
+        ));
+
+        e.execute_admin(&row(TransactionType::UNLOCK, 2).with_reason(reason))
+            .unwrap();
+
+        assert!(!e.get_client(1).unwrap().is_locked());
+        assert!(matches!(
+            e.execute_admin(&row(TransactionType::UNLOCK, 3).with_reason(reason)),
+            Err(EngineError::AccountNotLocked(1))
+        ));
+        assert_eq!(
+            e.lock_history(1),
+            [
+                LockChange {
+                    tx: 1,
+                    locked: true,
+                    reason: None
+                },
+                LockChange {
+                    tx: 2,
+                    locked: false,
+                    reason: Some(reason)
+                }
+            ]
+        );
+    }
+}
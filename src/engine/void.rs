@@ -0,0 +1,7 @@
+use super::{authorize, Engine};
+use crate::errors::EngineError;
+use crate::row::Row;
+
+pub fn execute(e: &mut Engine, row: &Row) -> Result<(), EngineError> {
+    authorize::close(e, row.tx, row.client)
+}
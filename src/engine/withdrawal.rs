@@ -1,75 +1,237 @@
-use super::Engine;
+use super::{fees, limits, DisputePolicy, DisputeRules, Engine, Reference, TransactionHandler};
 use crate::client::Client;
+use crate::decimal::Amount;
 use crate::errors::EngineError;
-use crate::transaction::{Transaction, TransactionDisputeStatus};
+use crate::row::{Action, Row};
+use crate::transaction::{Transaction, TransactionType};
+
+pub fn execute(e: &mut Engine, row: &Row) -> Result<(), EngineError> {
+    let Action::Withdrawal { amount, asset } = row.action else {
+        return Err(EngineError::InvalidTransactionType);
+    };
+    let fee = e.fees.fee_for(row.kind(), row.client, amount)?;
+    limits::check_withdrawal(e, row.client, asset, amount)?;
 
-pub fn execute(e: &mut Engine, transaction: &Transaction) -> Result<(), EngineError> {
     e.clients
-        .get_mut(&transaction.client)
-        .map_or(
-            Err(EngineError::ClientNotFound(transaction.client)),
-            |client| client.withdraw_funds(transaction.amount),
-        )
-        .and_then(|_| Ok(e.transactions.insert(transaction.tx, *transaction)))
-        .map(|_| ())
+        .get_mut(&row.client)
+        .ok_or(EngineError::ClientNotFound(row.client))?
+        .active()?
+        .withdraw_funds(asset, amount)?;
+    fees::charge(e, row.client, asset, fee)?;
+
+    if let Some(transaction) = Transaction::from_row(row) {
+        e.transactions
+            .insert(row.tx, Transaction { fee, ..transaction });
+    }
+    Ok(())
 }
 
-pub fn dispute(_: &mut Client, transaction: &mut Transaction) -> Result<(), EngineError> {
-    transaction.assure_status(TransactionDisputeStatus::NONE)?;
-
-    transaction.dispute_status = TransactionDisputeStatus::DISPUTED;
+/// A hold puts the withdrawn amount back on the account, as held funds
+pub fn dispute(
+    client: &mut Client,
+    transaction: &mut Transaction,
+    requested: Option<Amount>,
+    rules: DisputeRules,
+) -> Result<(), EngineError> {
+    rules.dispute.check(transaction.kind)?;
+    let amount = transaction.dispute_amount(requested)?;
+
+    if rules.dispute == DisputePolicy::Hold {
+        let client = client.active()?;
+        client.deposit_funds(transaction.asset, amount)?;
+        client.hold_funds(transaction.asset, amount)?;
+    }
+    transaction.record_dispute(amount)?;
 
     Ok(())
 }
 
-pub fn resolve(_: &mut Client, transaction: &mut Transaction) -> Result<(), EngineError> {
-    transaction.assure_status(TransactionDisputeStatus::DISPUTED)?;
+/// The withdrawal stands, held funds leave the account again
+pub fn resolve(
+    client: &mut Client,
+    transaction: &mut Transaction,
+    requested: Option<Amount>,
+    rules: DisputeRules,
+) -> Result<(), EngineError> {
+    rules.resolve.check(transaction.kind)?;
+    let amount = transaction.settle_amount(requested)?;
+
+    if rules.dispute == DisputePolicy::Hold {
+        client.withdraw_held_funds(transaction.asset, amount)?;
+    }
+    transaction.record_resolve(amount)?;
+
+    Ok(())
+}
 
-    transaction.dispute_status = TransactionDisputeStatus::NONE;
+/// The withdrawn amount is credited back to the client
+pub fn revert(
+    client: &mut Client,
+    transaction: &mut Transaction,
+    requested: Option<Amount>,
+    rules: DisputeRules,
+) -> Result<(), EngineError> {
+    let amount = transaction.settle_amount(requested)?;
+
+    match rules.dispute {
+        DisputePolicy::Hold => client.release_funds(transaction.asset, amount)?,
+        _ => client.refund_funds(transaction.asset, amount)?,
+    };
+    rules.chargeback.apply(client)?;
+    transaction.record_chargeback(amount)?;
 
     Ok(())
 }
 
-pub fn revert(client: &mut Client, transaction: &mut Transaction) -> Result<(), EngineError> {
-    transaction.assure_status(TransactionDisputeStatus::DISPUTED)?;
+pub struct Handler;
 
-    client.deposit_funds(transaction.amount)?;
-    client.lock()?;
-    transaction.dispute_status = TransactionDisputeStatus::REVERSED;
+impl TransactionHandler for Handler {
+    fn is_stored(&self) -> bool {
+        true
+    }
 
-    Ok(())
+    fn execute(&self, e: &mut Engine, row: &Row) -> Result<(), EngineError> {
+        execute(e, row)
+    }
+
+    fn dispute(&self, e: &mut Engine, row: &Reference) -> Result<(), EngineError> {
+        let rules = e.config.dispute_rules(TransactionType::WITHDRAWAL)?;
+        let (target, client) = e.get_mut_transaction_client_pair(row.tx, row.client)?;
+
+        dispute(client, target, row.amount, rules)
+    }
+
+    fn resolve(&self, e: &mut Engine, row: &Reference) -> Result<(), EngineError> {
+        let rules = e.config.dispute_rules(TransactionType::WITHDRAWAL)?;
+        let (target, client) = e.get_mut_transaction_client_pair(row.tx, row.client)?;
+
+        resolve(client, target, row.amount, rules)
+    }
+
+    fn revert(&self, e: &mut Engine, row: &Reference) -> Result<(), EngineError> {
+        let rules = e.config.dispute_rules(TransactionType::WITHDRAWAL)?;
+        let (target, client) = e.get_mut_transaction_client_pair(row.tx, row.client)?;
+
+        revert(client, target, row.amount, rules)
+    }
 }
 
 #[cfg(test)]
 mod tests {
     use crate::{
+        asset::Asset,
         client::Client,
         decimal::Decimal,
-        engine::Engine,
-        transaction::{Transaction, TransactionType},
+        engine::{
+            testing::{amount_row, row},
+            ChargebackPolicy, DisputePolicy, DisputeRules, Engine, EngineConfig, ResolvePolicy,
+        },
+        errors::EngineError,
+        transaction::TransactionType,
     };
 
     use super::execute;
 
+    /// Deposits 100, withdraws 40 and disputes the withdrawal
+    fn disputed_withdrawal(
+        dispute: DisputePolicy,
+        chargeback: ChargebackPolicy,
+    ) -> (Engine, Result<(), EngineError>) {
+        let config = EngineConfig::default().with_dispute_rules(
+            TransactionType::WITHDRAWAL,
+            DisputeRules {
+                dispute,
+                resolve: ResolvePolicy::Reinstate,
+                chargeback,
+            },
+        );
+        let mut e = Engine::new(config);
+
+        e.execute(&row(TransactionType::DEPOSIT, 1, 1, 100))
+            .unwrap();
+        e.execute(&row(TransactionType::WITHDRAWAL, 1, 2, 40))
+            .unwrap();
+        let result = e.execute(&row(TransactionType::DISPUTE, 1, 2, 0));
+
+        (e, result)
+    }
+
+    fn funds(e: &Engine) -> (Decimal<4>, Decimal<4>) {
+        let funds = e.get_client(1).unwrap().get_funds(Asset::default());
+        (funds.available, funds.held)
+    }
+
     #[test]
     fn correct_execution() {
-        let mut e = Engine::new();
+        let mut e = Engine::default();
 
         e.clients
             .entry(1)
             .or_insert(Client::new(1))
-            .deposit_funds(Decimal::from(100))
+            .active()
+            .unwrap()
+            .deposit_funds(Asset::default(), Decimal::from(100))
             .unwrap();
 
         execute(
             &mut e,
-            &Transaction::new(TransactionType::WITHDRAWAL, 1, 1, Decimal::from(50)),
+            &amount_row(TransactionType::WITHDRAWAL, 1, 1, Decimal::from(50)),
         )
         .unwrap();
 
         assert_eq!(
-            e.get_client(1).unwrap().get_funds().available,
+            e.get_client(1)
+                .unwrap()
+                .get_funds(Asset::default())
+                .available,
             Decimal::from(50)
         );
     }
+
+    #[test]
+    fn hold_withdrawn_amount() {
+        let (mut e, result) = disputed_withdrawal(DisputePolicy::Hold, ChargebackPolicy::Lock);
+        let row = |kind| amount_row(kind, 1, 2, Decimal::zero());
+
+        result.unwrap();
+        assert_eq!(funds(&e), (Decimal::from(60), Decimal::from(40)));
+
+        e.execute(&row(TransactionType::RESOLVE)).unwrap();
+        assert_eq!(funds(&e), (Decimal::from(60), Decimal::zero()));
+
+        e.execute(&row(TransactionType::DISPUTE)).unwrap();
+        e.execute(&row(TransactionType::CHARGEBACK)).unwrap();
+        assert_eq!(funds(&e), (Decimal::from(100), Decimal::zero()));
+        assert!(e.get_client(1).unwrap().is_locked());
+    }
+
+    #[test]
+    fn reject_withdrawal_disputes() {
+        let (e, result) = disputed_withdrawal(DisputePolicy::Reject, ChargebackPolicy::Lock);
+
+        assert!(matches!(
+            result,
+            Err(EngineError::DisputeRejected(TransactionType::WITHDRAWAL))
+        ));
+        assert_eq!(funds(&e), (Decimal::from(60), Decimal::zero()));
+    }
+
+    #[test]
+    fn credit_without_locking() {
+        let (mut e, result) =
+            disputed_withdrawal(DisputePolicy::Record, ChargebackPolicy::KeepActive);
+
+        result.unwrap();
+        assert_eq!(funds(&e), (Decimal::from(60), Decimal::zero()));
+
+        e.execute(&amount_row(
+            TransactionType::CHARGEBACK,
+            1,
+            2,
+            Decimal::zero(),
+        ))
+        .unwrap();
+        assert_eq!(funds(&e), (Decimal::from(100), Decimal::zero()));
+        assert!(!e.get_client(1).unwrap().is_locked());
+    }
 }
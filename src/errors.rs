@@ -1,18 +1,51 @@
 use std::{error::Error, fmt::Display};
 
-use crate::{client::ClientId, decimal::Decimal, transaction::TransactionId};
+use crate::{
+    client::{ClientId, Freeze, Reason},
+    decimal::Amount,
+    transaction::{CustomKind, TransactionId, TransactionType},
+};
 
-#[derive(Debug)]
+#[derive(Debug, Clone, Copy, PartialEq)]
 pub enum EngineError {
     ClientNotFound(ClientId),
     TransactionNotFound(TransactionId),
+    DuplicateTransaction(TransactionId),
+    TransactionNotOwned(TransactionId, ClientId),
     TransactionInvalidStatus(TransactionId),
-    InsufficientFunds(ClientId, Decimal<4>, Decimal<4>),
+    DisputeAmountTooLarge(TransactionId, Amount),
+    DisputeRejected(TransactionType),
+    ResolveRejected(TransactionType),
+    InsufficientFunds(ClientId, Amount, Amount),
     AccountLocked(ClientId),
-    NegativeAmount(Decimal<4>),
+    AccountNotLocked(ClientId),
+    AccountFrozen(ClientId, Freeze, Reason),
+    AccountNotFrozen(ClientId, Freeze),
+    NegativeAmount(Amount),
+    Overflow(ClientId),
     InvalidTransactionType,
+    InvalidAsset,
+    InvalidDestination(TransactionId),
+    InvalidReason,
+    InvalidTier,
+    InvalidFee(TransactionType),
+    MissingHouse,
+    ReservedClient(ClientId),
+    AdminOnly(TransactionType),
+    MissingReason(TransactionId),
+    AuthorizationClosed(TransactionId),
+    CaptureAmountTooLarge(TransactionId, Amount),
+    WithdrawalLimitExceeded(ClientId, Amount),
+    WithdrawalTotalLimitExceeded(ClientId, Amount),
+    BalanceLimitExceeded(ClientId, Amount),
+    DepositCountLimitExceeded(ClientId, u32),
+    InvalidLimit,
+    ReplayDiverged(TransactionId),
+    /// Rows are refused once the journal failed to record one
+    JournalFailed,
     IOError(&'static str),
     DeserializationError(&'static str),
+    DecimalError(DecimalError),
 }
 
 impl Display for EngineError {
@@ -22,6 +55,16 @@ impl Display for EngineError {
             EngineError::TransactionNotFound(tx) => {
                 write!(f, "Transaction with id {} not found.", tx)
             }
+            EngineError::TransactionNotOwned(tx, c) => {
+                write!(f, "Transaction {} does not belong to client {}.", tx, c)
+            }
+            EngineError::DuplicateTransaction(tx) => {
+                write!(
+                    f,
+                    "Transaction id {} was already used by another transaction.",
+                    tx
+                )
+            }
             EngineError::InsufficientFunds(c, balance, amount) => write!(
                 f,
                 "Client {} balance (= {}) < requested amount (= {})",
@@ -30,12 +73,104 @@ impl Display for EngineError {
             EngineError::AccountLocked(c) => {
                 write!(f, "Client with id {} has the account locked.", c)
             }
+            EngineError::AccountNotLocked(c) => {
+                write!(f, "Client with id {} does not have the account locked.", c)
+            }
+            EngineError::AccountFrozen(c, freeze, reason) => write!(
+                f,
+                "Client with id {} has the account frozen ({:?}, reason {}).",
+                c, freeze, reason
+            ),
+            EngineError::AccountNotFrozen(c, freeze) => write!(
+                f,
+                "Client with id {} does not have a {:?} freeze.",
+                c, freeze
+            ),
             EngineError::NegativeAmount(a) => {
                 write!(f, "Amount {} must be greater or equal to zero.", a)
             }
+            EngineError::Overflow(c) => {
+                write!(f, "Client {} balance would overflow.", c)
+            }
             EngineError::InvalidTransactionType => write!(f, "Invalid transaction type"),
+            EngineError::InvalidAsset => write!(f, "Invalid asset code."),
+            EngineError::InvalidDestination(tx) => {
+                write!(f, "Transfer {} has a missing or invalid destination.", tx)
+            }
+            EngineError::InvalidReason => write!(f, "Invalid reason code."),
+            EngineError::InvalidTier => write!(f, "Invalid client tier."),
+            EngineError::InvalidFee(kind) => {
+                write!(f, "Fee for {} transactions can't be negative.", kind)
+            }
+            EngineError::MissingHouse => write!(f, "Fees require a house account."),
+            EngineError::ReservedClient(c) => {
+                write!(f, "Client id {} is reserved for the house account.", c)
+            }
+            EngineError::AdminOnly(kind) => {
+                write!(f, "Only the admin source may issue {} rows.", kind)
+            }
+            EngineError::AuthorizationClosed(tx) => {
+                write!(f, "Authorization {} is not open anymore.", tx)
+            }
+            EngineError::CaptureAmountTooLarge(tx, open) => write!(
+                f,
+                "Amount exceeds what is still authorized (= {}) by {}.",
+                open, tx
+            ),
+            EngineError::WithdrawalLimitExceeded(c, max) => write!(
+                f,
+                "Client {} withdrawal exceeds the maximum single withdrawal (= {}).",
+                c, max
+            ),
+            EngineError::WithdrawalTotalLimitExceeded(c, max) => write!(
+                f,
+                "Client {} withdrawals exceed the rolling withdrawal limit (= {}).",
+                c, max
+            ),
+            EngineError::BalanceLimitExceeded(c, max) => write!(
+                f,
+                "Client {} balance would exceed the maximum balance (= {}).",
+                c, max
+            ),
+            EngineError::DepositCountLimitExceeded(c, max) => write!(
+                f,
+                "Client {} exceeds the maximum number of deposits (= {}).",
+                c, max
+            ),
+            EngineError::InvalidLimit => write!(f, "Invalid limit setting."),
+            EngineError::ReplayDiverged(tx) => write!(
+                f,
+                "Replaying transaction {} did not give the logged outcome.",
+                tx
+            ),
+            EngineError::JournalFailed => {
+                write!(f, "Processing stopped after the journal failed.")
+            }
+            EngineError::MissingReason(tx) => {
+                write!(f, "Transaction {} requires a reason.", tx)
+            }
             EngineError::IOError(m) => write!(f, "IO Error: {}", m),
             EngineError::DeserializationError(m) => write!(f, "Deserialization error: {}.", m),
+            EngineError::DecimalError(e) => e.fmt(f),
+            EngineError::DisputeAmountTooLarge(id, limit) => write!(
+                f,
+                "Amount exceeds what can be disputed or settled (= {}) on transaction {}.",
+                limit, id
+            ),
+            EngineError::DisputeRejected(kind) => {
+                write!(
+                    f,
+                    "{} transactions can't be disputed.",
+                    kind.name().to_uppercase()
+                )
+            }
+            EngineError::ResolveRejected(kind) => {
+                write!(
+                    f,
+                    "Disputes of {} transactions can't be resolved.",
+                    kind.name().to_uppercase()
+                )
+            }
             EngineError::TransactionInvalidStatus(id) => {
                 write!(f, "Invalid transaction {} status.", id)
             }
@@ -43,4 +178,138 @@ impl Display for EngineError {
     }
 }
 
+impl EngineError {
+    /// Name of the variant, which stays the same when messages are reworded
+    pub fn code(&self) -> &'static str {
+        match self {
+            EngineError::ClientNotFound(..) => "ClientNotFound",
+            EngineError::TransactionNotFound(..) => "TransactionNotFound",
+            EngineError::DuplicateTransaction(..) => "DuplicateTransaction",
+            EngineError::TransactionNotOwned(..) => "TransactionNotOwned",
+            EngineError::TransactionInvalidStatus(..) => "TransactionInvalidStatus",
+            EngineError::DisputeAmountTooLarge(..) => "DisputeAmountTooLarge",
+            EngineError::DisputeRejected(..) => "DisputeRejected",
+            EngineError::ResolveRejected(..) => "ResolveRejected",
+            EngineError::InsufficientFunds(..) => "InsufficientFunds",
+            EngineError::AccountLocked(..) => "AccountLocked",
+            EngineError::AccountNotLocked(..) => "AccountNotLocked",
+            EngineError::AccountFrozen(..) => "AccountFrozen",
+            EngineError::AccountNotFrozen(..) => "AccountNotFrozen",
+            EngineError::NegativeAmount(..) => "NegativeAmount",
+            EngineError::Overflow(..) => "Overflow",
+            EngineError::InvalidTransactionType => "InvalidTransactionType",
+            EngineError::InvalidAsset => "InvalidAsset",
+            EngineError::InvalidDestination(..) => "InvalidDestination",
+            EngineError::InvalidReason => "InvalidReason",
+            EngineError::InvalidTier => "InvalidTier",
+            EngineError::InvalidFee(..) => "InvalidFee",
+            EngineError::MissingHouse => "MissingHouse",
+            EngineError::ReservedClient(..) => "ReservedClient",
+            EngineError::AdminOnly(..) => "AdminOnly",
+            EngineError::MissingReason(..) => "MissingReason",
+            EngineError::AuthorizationClosed(..) => "AuthorizationClosed",
+            EngineError::CaptureAmountTooLarge(..) => "CaptureAmountTooLarge",
+            EngineError::WithdrawalLimitExceeded(..) => "WithdrawalLimitExceeded",
+            EngineError::WithdrawalTotalLimitExceeded(..) => "WithdrawalTotalLimitExceeded",
+            EngineError::BalanceLimitExceeded(..) => "BalanceLimitExceeded",
+            EngineError::DepositCountLimitExceeded(..) => "DepositCountLimitExceeded",
+            EngineError::InvalidLimit => "InvalidLimit",
+            EngineError::ReplayDiverged(..) => "ReplayDiverged",
+            EngineError::JournalFailed => "JournalFailed",
+            EngineError::IOError(..) => "IOError",
+            EngineError::DeserializationError(..) => "DeserializationError",
+            EngineError::DecimalError(..) => "DecimalError",
+        }
+    }
+}
+
 impl Error for EngineError {}
+
+/// A row changed the state but could not be appended to the journal, which
+/// is behind the engine from then on
+#[derive(Debug, Clone, PartialEq)]
+pub struct JournalError {
+    pub tx: TransactionId,
+    pub cause: String,
+}
+
+impl Display for JournalError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "Could not append transaction {} to the journal: {}",
+            self.tx, self.cause
+        )
+    }
+}
+
+impl Error for JournalError {}
+
+impl From<DecimalError> for EngineError {
+    fn from(e: DecimalError) -> Self {
+        EngineError::DecimalError(e)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DecimalError {
+    Malformed,
+    TooManyFractionDigits(u32),
+    OutOfRange,
+    Overflow,
+    DivisionByZero,
+    ScaleMismatch(u32, u32),
+}
+
+impl Display for DecimalError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match *self {
+            DecimalError::Malformed => write!(f, "Malformed decimal number."),
+            DecimalError::TooManyFractionDigits(p) => {
+                write!(f, "Decimal number has more than {} fraction digits.", p)
+            }
+            DecimalError::OutOfRange => write!(f, "Decimal number is out of range."),
+            DecimalError::Overflow => write!(f, "Decimal arithmetic overflow."),
+            DecimalError::DivisionByZero => write!(f, "Decimal division by zero."),
+            DecimalError::ScaleMismatch(a, b) => {
+                write!(f, "Decimal scales {} and {} do not match.", a, b)
+            }
+        }
+    }
+}
+
+impl Error for DecimalError {}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RowError {
+    MissingField(TransactionType, &'static str),
+    UnexpectedField(TransactionType, &'static str),
+    /// A type no handler was registered for
+    UnknownType(CustomKind),
+}
+
+impl Display for RowError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match *self {
+            RowError::MissingField(kind, field) => {
+                write!(
+                    f,
+                    "{} row is missing the {} field.",
+                    kind.name().to_uppercase(),
+                    field
+                )
+            }
+            RowError::UnexpectedField(kind, field) => {
+                write!(
+                    f,
+                    "{} row has an unexpected {} field.",
+                    kind.name().to_uppercase(),
+                    field
+                )
+            }
+            RowError::UnknownType(kind) => write!(f, "Unknown transaction type {}.", kind),
+        }
+    }
+}
+
+impl Error for RowError {}
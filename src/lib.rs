@@ -0,0 +1,19 @@
+//! Transaction engine processing deposits, withdrawals, transfers and their
+//! disputes for a set of client accounts. The binary reads the rows from CSV
+//! files; other crates can drive an `engine::Engine` directly, register their
+//! own `engine::TransactionHandler`s and subscribe to its events.
+#[cfg(test)]
+extern crate quickcheck;
+#[cfg(test)]
+#[macro_use(quickcheck)]
+extern crate quickcheck_macros;
+
+pub mod asset;
+pub mod client;
+pub mod code;
+pub mod decimal;
+pub mod engine;
+pub mod errors;
+pub mod report;
+pub mod row;
+pub mod transaction;
@@ -1,65 +1,284 @@
-#[cfg(test)]
-extern crate quickcheck;
-#[cfg(test)]
-#[macro_use(quickcheck)]
-extern crate quickcheck_macros;
-
-pub mod client;
-pub mod decimal;
-pub mod engine;
-pub mod errors;
-pub mod transaction;
-
 use std::env::args;
 use std::error::Error;
-use std::fs::File;
+use std::fs::{self, File};
 use std::io::{stdout, BufReader};
 use std::process;
 
-use csv::Writer;
-use engine::Engine;
-use errors::EngineError;
+use simple_transaction_engine::client::ClientId;
+use simple_transaction_engine::decimal::DecimalFormat;
+use simple_transaction_engine::engine::{
+    parse_dispute_rules, Engine, EngineConfig, FeeRefund, FeeSchedule, Journal, LimitSchedule,
+};
+use simple_transaction_engine::errors::{EngineError, JournalError, RowError};
+use simple_transaction_engine::report::{self, Layout};
+use simple_transaction_engine::row::{Action, Row};
+use simple_transaction_engine::transaction::TransactionId;
+
+const USAGE: &str = "[<input csv>] [--admin <csv>] [--layout wide|long] [--amounts trimmed|full] \
+                     [--fees <csv>] [--tiers <csv>] \
+                     [--house <client>] [--refund-fees] [--limits <csv>] \
+                     [--credit-lines <csv>] [--negative-balance allow|reject|cap] \
+                     [--disputes <type>=<dispute>[[/<resolve>]/<chargeback>]]... [--journal <csv>] \
+                     [--replay <csv> [--replay-until <tx>]] [--state-in <file>] \
+                     [--state-out <file>] [--totals <csv>]";
 
-use crate::transaction::Transaction;
+struct Options {
+    /// Optional when replaying a journal or restoring a snapshot
+    input: Option<String>,
+    /// Rows of any type, executed before the input
+    admin: Option<String>,
+    layout: Layout,
+    amounts: DecimalFormat,
+    fees: Option<String>,
+    tiers: Option<String>,
+    house: Option<ClientId>,
+    refund_fees: bool,
+    limits: Option<String>,
+    credit_lines: Option<String>,
+    config: EngineConfig,
+    journal: Option<String>,
+    replay: Option<String>,
+    replay_until: Option<TransactionId>,
+    state_in: Option<String>,
+    state_out: Option<String>,
+    /// Per-asset sums over every account, for reconciliation
+    totals: Option<String>,
+}
 
 fn main() -> Result<(), EngineError> {
     let args: Vec<String> = args().collect();
-    if args.len() < 2 {
-        println!("Usage: {} <input csv>", args[0]);
+    let options = parse_options(&args[1..]).unwrap_or_else(|e| {
+        println!("{}", e);
+        println!("Usage: {} {}", args[0], USAGE);
         process::exit(1);
+    });
+
+    let fees = load_fee_schedule(&options)
+        .map_err(|_| EngineError::IOError("Could not read the fee schedule."))?;
+    let limits =
+        load_limits(&options).map_err(|_| EngineError::IOError("Could not read the limits."))?;
+    let mut engine = Engine::new(options.config)
+        .with_fees(fees)
+        .with_limits(limits);
+    // Restoring replaces the accounts, credit lines are set on top of them
+    if let Some(filename) = &options.state_in {
+        File::open(filename)
+            .map_err(Box::from)
+            .and_then(|f| engine.restore(BufReader::new(f)))
+            .map_err(|_| EngineError::IOError("Could not restore the engine state."))?;
+    }
+    if let Some(filename) = &options.credit_lines {
+        File::open(filename)
+            .map_err(Box::from)
+            .and_then(|f| engine.load_credit_lines(BufReader::new(f)))
+            .map_err(|_| EngineError::IOError("Could not read the credit lines."))?;
+    }
+    if let Some(filename) = &options.replay {
+        File::open(filename)
+            .map_err(Box::from)
+            .and_then(|f| engine.replay(BufReader::new(f), options.replay_until))
+            .map_err(|_| EngineError::IOError("Could not replay the journal."))?;
+    }
+    if let Some(filename) = &options.journal {
+        let journal = Journal::open(filename)
+            .map_err(|_| EngineError::IOError("Could not open the journal."))?;
+        engine = engine.with_journal(journal);
+    }
+    if let Some(filename) = &options.admin {
+        load_transactions(&mut engine, filename, true)
+            .map_err(|e| load_error(e, "Could not open admin file."))?;
+    }
+    if let Some(filename) = &options.input {
+        load_transactions(&mut engine, filename, false)
+            .map_err(|e| load_error(e, "Could not open input file."))?;
+    }
+    if let Some(filename) = &options.state_out {
+        save_state(&engine, filename)
+            .map_err(|_| EngineError::IOError("Could not save the engine state."))?;
+    }
+    if let Some(filename) = &options.totals {
+        File::create(filename)
+            .map_err(Box::from)
+            .and_then(|f| report::write_totals(&engine, options.amounts, f))
+            .map_err(|_| EngineError::IOError("Could not write the totals."))?;
+    }
+    report::write_accounts(&engine, options.layout, options.amounts, stdout())
+        .map_err(|_| EngineError::IOError("Could not write output file."))
+}
+
+fn parse_options(args: &[String]) -> Result<Options, String> {
+    let mut options = Options {
+        input: None,
+        admin: None,
+        layout: Layout::default(),
+        amounts: DecimalFormat::default(),
+        fees: None,
+        tiers: None,
+        house: None,
+        refund_fees: false,
+        limits: None,
+        credit_lines: None,
+        config: EngineConfig::default(),
+        journal: None,
+        replay: None,
+        replay_until: None,
+        state_in: None,
+        state_out: None,
+        totals: None,
+    };
+
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        let mut value = || {
+            args.next()
+                .cloned()
+                .ok_or(format!("Missing value for {}.", arg))
+        };
+
+        match arg.as_str() {
+            "--admin" => options.admin = Some(value()?),
+            "--layout" => options.layout = value()?.parse()?,
+            "--amounts" => options.amounts = value()?.parse()?,
+            "--fees" => options.fees = Some(value()?),
+            "--tiers" => options.tiers = Some(value()?),
+            "--house" => {
+                options.house = Some(value()?.parse().map_err(|_| "Invalid house client id.")?)
+            }
+            "--refund-fees" => options.refund_fees = true,
+            "--limits" => options.limits = Some(value()?),
+            "--credit-lines" => options.credit_lines = Some(value()?),
+            "--negative-balance" => options.config.negative_balance = value()?.parse()?,
+            "--disputes" => {
+                let (kind, rules) = parse_dispute_rules(&value()?)?;
+                options.config.set_dispute_rules(kind, rules);
+            }
+            "--journal" => options.journal = Some(value()?),
+            "--replay" => options.replay = Some(value()?),
+            "--replay-until" => {
+                options.replay_until =
+                    Some(value()?.parse().map_err(|_| "Invalid transaction id.")?)
+            }
+            "--state-in" => options.state_in = Some(value()?),
+            "--state-out" => options.state_out = Some(value()?),
+            "--totals" => options.totals = Some(value()?),
+            _ if options.input.is_none() => options.input = Some(arg.clone()),
+            _ => return Err(format!("Unexpected argument {}.", arg)),
+        }
+    }
+
+    if options.input.is_none()
+        && options.admin.is_none()
+        && options.replay.is_none()
+        && options.state_in.is_none()
+    {
+        return Err("Missing input file.".to_string());
+    }
+    if options.replay_until.is_some() && options.replay.is_none() {
+        return Err("--replay-until requires --replay.".to_string());
+    }
+
+    Ok(options)
+}
+
+fn load_fee_schedule(options: &Options) -> Result<FeeSchedule, Box<dyn Error>> {
+    let refund = if options.refund_fees {
+        FeeRefund::OnChargeback
+    } else {
+        FeeRefund::Never
+    };
+    let fees = match options.house {
+        Some(house) => FeeSchedule::new(house),
+        None => FeeSchedule::default(),
+    };
+    let mut fees = fees.with_refund(refund);
+
+    if let Some(filename) = &options.fees {
+        fees.load_fees(BufReader::new(File::open(filename)?))?;
+    }
+    if let Some(filename) = &options.tiers {
+        fees.load_tiers(BufReader::new(File::open(filename)?))?;
     }
 
-    let mut engine = Engine::new();
-    load_transactions(&mut engine, &args[1])
-        .map_err(|_| EngineError::IOError("Could not open input file."))?;
-    dump_accounts(&mut engine).map_err(|_| EngineError::IOError("Could not write output file."))
+    Ok(fees)
 }
 
-fn load_transactions(engine: &mut Engine, filename: &String) -> Result<(), Box<dyn Error>> {
+fn load_limits(options: &Options) -> Result<LimitSchedule, Box<dyn Error>> {
+    let mut limits = LimitSchedule::default();
+
+    if let Some(filename) = &options.limits {
+        limits.load(BufReader::new(File::open(filename)?))?;
+    }
+
+    Ok(limits)
+}
+
+/// Rows of admin types are only accepted from an `admin` file
+fn load_transactions(
+    engine: &mut Engine,
+    filename: &String,
+    admin: bool,
+) -> Result<(), Box<dyn Error>> {
     let mut rdr = File::open(filename).map(|f| csv::Reader::from_reader(BufReader::new(f)))?;
 
-    for result in rdr.deserialize() {
-        // Ignore invalid entries
-        if result.is_err() {
-            continue;
+    for result in rdr.deserialize::<Row>() {
+        // Report and skip malformed entries
+        let row = match result {
+            Ok(row) => row,
+            Err(e) => {
+                eprintln!("Skipping invalid entry: {}", e);
+                continue;
+            }
+        };
+        if let Action::Custom { kind, .. } = row.action {
+            if !engine.has_handler(row.kind()) {
+                eprintln!("Skipping invalid entry: {}", RowError::UnknownType(kind));
+                continue;
+            }
         }
 
-        let transaction: Transaction = result.unwrap();
-        if let Err(_) = engine.execute(&transaction) {
+        let result = if admin {
+            engine.execute_admin(&row)
+        } else {
+            engine.execute(&row)
+        };
+        if result.is_err() {
             // Ignore invalid transactions
         }
+        // The journal is behind the state, nothing may run after this row
+        if let Some(failure) = engine.journal_failure() {
+            return Err(failure.clone().into());
+        }
     }
 
     Ok(())
 }
 
-fn dump_accounts(engine: &mut Engine) -> Result<(), Box<dyn Error>> {
-    let mut wtr = Writer::from_writer(stdout());
+/// Writes the snapshot next to `filename` and renames it into place, so that
+/// a failed run leaves the previous state intact
+fn save_state(engine: &Engine, filename: &String) -> Result<(), Box<dyn Error>> {
+    let temp = format!("{}.tmp", filename);
+    let result = File::create(&temp)
+        .map_err(Box::from)
+        .and_then(|f| {
+            engine.snapshot(&f)?;
+            f.sync_all()?;
+            Ok(())
+        })
+        .and_then(|_| fs::rename(&temp, filename).map_err(Box::from));
 
-    for (_, client) in engine.iter_clients() {
-        wtr.serialize(client)?;
+    if result.is_err() {
+        let _ = fs::remove_file(&temp);
     }
+    result
+}
 
-    wtr.flush()?;
-    Ok(())
+/// Journal failures are reported as such, anything else as `message`
+fn load_error(error: Box<dyn Error>, message: &'static str) -> EngineError {
+    match error.downcast_ref::<JournalError>() {
+        Some(failure) => {
+            eprintln!("{}", failure);
+            EngineError::JournalFailed
+        }
+        None => EngineError::IOError(message),
+    }
 }
@@ -0,0 +1,359 @@
+/**
+ * Writes the client accounts as CSV, amounts either trimmed ("1.5") or with
+ * every fraction digit ("1.5000"). Two layouts are supported:
+ * - wide: one row per client, with an `available/held/total` group of columns
+ *   for every asset (the default asset keeps the unprefixed column names)
+ * - long: one row per (client, asset)
+ *
+ * Once any client has a credit line or a negative available balance (e.g.
+ * after a dispute held more than was left), an `overdraft` column with the
+ * amount below zero follows the totals. Likewise, once any client owes a charged back
+ * dispute shortfall, a `receivable` column follows.
+ *
+ * The totals report has one row per asset with its sums over every account.
+ */
+use crate::{
+    asset::Asset,
+    client::{Client, ClientId, Funds},
+    decimal::{Amount, DecimalFormat},
+    engine::Engine,
+};
+use csv::Writer;
+use serde::Serialize;
+use std::{collections::BTreeSet, error::Error, io::Write, str::FromStr};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Layout {
+    #[default]
+    Wide,
+    Long,
+}
+
+impl FromStr for Layout {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "wide" => Ok(Layout::Wide),
+            "long" => Ok(Layout::Long),
+            _ => Err(format!("Unknown layout {}, expected wide or long.", s)),
+        }
+    }
+}
+
+#[derive(Serialize)]
+struct LongRow {
+    client: ClientId,
+    asset: Asset,
+    available: String,
+    held: String,
+    total: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    overdraft: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    receivable: Option<String>,
+    locked: bool,
+}
+
+pub fn write_accounts<W: Write>(
+    engine: &Engine,
+    layout: Layout,
+    format: DecimalFormat,
+    writer: W,
+) -> Result<(), Box<dyn Error>> {
+    let mut wtr = Writer::from_writer(writer);
+
+    match layout {
+        Layout::Wide => write_wide(engine, format, &mut wtr)?,
+        Layout::Long => write_long(engine, format, &mut wtr)?,
+    }
+
+    wtr.flush()?;
+    Ok(())
+}
+
+#[derive(Serialize)]
+struct TotalsRow {
+    asset: Asset,
+    available: String,
+    held: String,
+    total: String,
+}
+
+/// Sums are wide, a ledger may hold more than a single balance can
+pub fn write_totals<W: Write>(
+    engine: &Engine,
+    format: DecimalFormat,
+    writer: W,
+) -> Result<(), Box<dyn Error>> {
+    let mut wtr = Writer::from_writer(writer);
+
+    for asset in assets(engine) {
+        let totals = engine.total_funds(asset)?;
+        wtr.serialize(TotalsRow {
+            asset,
+            available: totals.available.format(format),
+            held: totals.held.format(format),
+            total: totals.total.format(format),
+        })?;
+    }
+
+    wtr.flush()?;
+    Ok(())
+}
+
+fn write_long<W: Write>(
+    engine: &Engine,
+    format: DecimalFormat,
+    wtr: &mut Writer<W>,
+) -> Result<(), Box<dyn Error>> {
+    let overdraft = has_overdrafts(engine);
+    let receivable = has_receivables(engine);
+
+    for (_, client) in engine.iter_clients() {
+        for (asset, funds) in client_funds(client) {
+            wtr.serialize(LongRow {
+                client: client.id(),
+                asset,
+                available: funds.available.format(format),
+                held: funds.held.format(format),
+                total: funds.total()?.format(format),
+                overdraft: overdraft
+                    .then(|| funds.overdraft().map(|o| o.format(format)))
+                    .transpose()?,
+                receivable: receivable.then(|| client.get_receivable(asset).format(format)),
+                locked: client.is_locked(),
+            })?;
+        }
+    }
+
+    Ok(())
+}
+
+fn write_wide<W: Write>(
+    engine: &Engine,
+    format: DecimalFormat,
+    wtr: &mut Writer<W>,
+) -> Result<(), Box<dyn Error>> {
+    let assets = assets(engine);
+    let overdraft = has_overdrafts(engine);
+    let receivable = has_receivables(engine);
+    let mut columns = vec!["available", "held", "total"];
+    if overdraft {
+        columns.push("overdraft");
+    }
+    if receivable {
+        columns.push("receivable");
+    }
+
+    let mut header = vec!["client".to_string()];
+    for asset in &assets {
+        for column in &columns {
+            header.push(if asset.is_default() {
+                column.to_string()
+            } else {
+                format!("{}_{}", asset, column)
+            });
+        }
+    }
+    header.push("locked".to_string());
+    wtr.write_record(&header)?;
+
+    for (_, client) in engine.iter_clients() {
+        let mut row = vec![client.id().to_string()];
+        for asset in &assets {
+            let funds = client.get_funds(*asset);
+            row.push(funds.available.format(format));
+            row.push(funds.held.format(format));
+            row.push(funds.total()?.format(format));
+            if overdraft {
+                row.push(funds.overdraft()?.format(format));
+            }
+            if receivable {
+                row.push(client.get_receivable(*asset).format(format));
+            }
+        }
+        row.push(client.is_locked().to_string());
+        wtr.write_record(&row)?;
+    }
+
+    Ok(())
+}
+
+/// Every asset some account reports, in report order
+fn assets(engine: &Engine) -> BTreeSet<Asset> {
+    engine
+        .iter_clients()
+        .flat_map(|(_, client)| client_funds(client).map(|(asset, _)| asset))
+        .collect()
+}
+
+fn has_overdrafts(engine: &Engine) -> bool {
+    engine.iter_clients().any(|(_, client)| {
+        client.credit_limit() > Amount::zero()
+            || client
+                .iter_funds()
+                .any(|(_, funds)| funds.available < Amount::zero())
+    })
+}
+
+fn has_receivables(engine: &Engine) -> bool {
+    engine
+        .iter_clients()
+        .flat_map(|(_, client)| client.iter_receivables())
+        .any(|(_, receivable)| receivable > Amount::zero())
+}
+
+/// Clients without any balance still get reported with the default asset
+fn client_funds(client: &Client) -> impl Iterator<Item = (Asset, Funds)> + '_ {
+    let empty = client.iter_funds().next().is_none();
+
+    client
+        .iter_funds()
+        .chain(empty.then(|| (Asset::default(), Funds::default())))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{write_accounts, write_totals, Layout};
+    use crate::{
+        decimal::{Amount, Decimal, DecimalFormat},
+        engine::{
+            testing::{amount_row, row},
+            Engine, EngineConfig, NegativeBalancePolicy,
+        },
+        transaction::TransactionType,
+    };
+
+    fn engine() -> Engine {
+        let mut e = Engine::default();
+        e.execute(&row(TransactionType::DEPOSIT, 1, 1, 5)).unwrap();
+        e.execute(
+            &amount_row(TransactionType::DEPOSIT, 1, 2, "1.5".parse().unwrap())
+                .with_asset("BTC".parse().unwrap()),
+        )
+        .unwrap();
+        e
+    }
+
+    fn render(layout: Layout) -> String {
+        render_engine(&engine(), layout)
+    }
+
+    fn render_engine(e: &Engine, layout: Layout) -> String {
+        let mut out = Vec::new();
+        write_accounts(e, layout, DecimalFormat::Trimmed, &mut out).unwrap();
+        String::from_utf8(out).unwrap()
+    }
+
+    #[test]
+    fn wide_layout() {
+        assert_eq!(
+            render(Layout::Wide),
+            "client,available,held,total,BTC_available,BTC_held,BTC_total,locked\n\
+             1,5.0,0.0,5.0,1.5,0.0,1.5,false\n"
+        );
+    }
+
+    #[test]
+    fn long_layout() {
+        assert_eq!(
+            render(Layout::Long),
+            "client,asset,available,held,total,locked\n\
+             1,,5.0,0.0,5.0,false\n\
+             1,BTC,1.5,0.0,1.5,false\n"
+        );
+    }
+
+    #[test]
+    fn full_scale_amounts() {
+        let mut out = Vec::new();
+        write_accounts(&engine(), Layout::Long, DecimalFormat::FullScale, &mut out).unwrap();
+
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            "client,asset,available,held,total,locked\n\
+             1,,5.0000,0.0000,5.0000,false\n\
+             1,BTC,1.5000,0.0000,1.5000,false\n"
+        );
+    }
+
+    #[test]
+    fn overdraft_column() {
+        let mut e = engine();
+        e.set_credit_limit(2, Decimal::from(10)).unwrap();
+        e.execute(&row(TransactionType::WITHDRAWAL, 2, 3, 4))
+            .unwrap();
+
+        let wide = render_engine(&e, Layout::Wide);
+        assert!(wide.starts_with(
+            "client,available,held,total,overdraft,BTC_available,BTC_held,BTC_total,BTC_overdraft,locked\n"
+        ));
+        assert!(wide.contains("2,-4.0,0.0,-4.0,4.0,0.0,0.0,0.0,0.0,false\n"));
+
+        let long = render_engine(&e, Layout::Long);
+        assert!(long.starts_with("client,asset,available,held,total,overdraft,locked\n"));
+        assert!(long.contains("2,,-4.0,0.0,-4.0,4.0,false\n"));
+    }
+
+    #[test]
+    fn overdraft_without_credit_line() {
+        let mut e = engine();
+        e.execute(&row(TransactionType::WITHDRAWAL, 1, 3, 3))
+            .unwrap();
+        e.execute(&row(TransactionType::DISPUTE, 1, 1, 0)).unwrap();
+
+        assert_eq!(
+            render_engine(&e, Layout::Long),
+            "client,asset,available,held,total,overdraft,locked\n\
+             1,,-3.0,5.0,2.0,3.0,false\n\
+             1,BTC,1.5,0.0,1.5,0.0,false\n"
+        );
+    }
+
+    #[test]
+    fn receivable_column() {
+        let mut e = Engine::new(
+            EngineConfig::default().with_negative_balance_policy(NegativeBalancePolicy::Cap),
+        );
+        for (kind, tx, amount) in [
+            (TransactionType::DEPOSIT, 1, 10),
+            (TransactionType::WITHDRAWAL, 2, 4),
+            (TransactionType::DISPUTE, 1, 0),
+            (TransactionType::CHARGEBACK, 1, 0),
+        ] {
+            e.execute(&row(kind, 1, tx, amount)).unwrap();
+        }
+
+        assert_eq!(
+            render_engine(&e, Layout::Wide),
+            "client,available,held,total,receivable,locked\n\
+             1,0.0,0.0,0.0,4.0,true\n"
+        );
+        assert_eq!(
+            render_engine(&e, Layout::Long),
+            "client,asset,available,held,total,receivable,locked\n\
+             1,,0.0,0.0,0.0,4.0,true\n"
+        );
+    }
+
+    #[test]
+    fn totals_exceed_a_single_balance() {
+        let mut e = engine();
+        let large: Amount = "900000000000000".parse().unwrap();
+        e.execute(&amount_row(TransactionType::DEPOSIT, 2, 3, large))
+            .unwrap();
+        e.execute(&amount_row(TransactionType::DEPOSIT, 3, 4, large))
+            .unwrap();
+        e.execute(&row(TransactionType::DISPUTE, 1, 1, 0)).unwrap();
+
+        let mut out = Vec::new();
+        write_totals(&e, DecimalFormat::Trimmed, &mut out).unwrap();
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            "asset,available,held,total\n\
+             ,1800000000000000.0,5.0,1800000000000005.0\n\
+             BTC,1.5,0.0,1.5\n"
+        );
+    }
+}
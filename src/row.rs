@@ -0,0 +1,478 @@
+/**
+ * Typed view of an input CSV row. Every transaction type only accepts the
+ * columns it uses: a missing required value or a value in a column the type
+ * doesn't use is a parse error rather than a default.
+ */
+use crate::{
+    asset::Asset,
+    client::{ClientId, Freeze, Reason},
+    decimal::Amount,
+    engine::LimitSetting,
+    errors::RowError,
+    transaction::{CustomKind, Timestamp, TransactionId, TransactionType},
+};
+use serde::Deserialize;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(try_from = "RawRow")]
+pub struct Row {
+    pub client: ClientId,
+    pub tx: TransactionId,
+    /// Why the row was issued, required by unlocks and optional for the rest
+    pub reason: Option<Reason>,
+    pub timestamp: Option<Timestamp>,
+    pub action: Action,
+}
+
+/// Partial amounts (dispute family, capture) act on everything when absent
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Action {
+    Deposit {
+        amount: Amount,
+        asset: Asset,
+    },
+    Withdrawal {
+        amount: Amount,
+        asset: Asset,
+    },
+    Transfer {
+        amount: Amount,
+        asset: Asset,
+        destination: ClientId,
+    },
+    Dispute {
+        amount: Option<Amount>,
+    },
+    Resolve {
+        amount: Option<Amount>,
+    },
+    Chargeback {
+        amount: Option<Amount>,
+    },
+    Authorize {
+        amount: Amount,
+        asset: Asset,
+        expires: Option<Timestamp>,
+    },
+    Capture {
+        amount: Option<Amount>,
+    },
+    Void,
+    Unlock,
+    Limit {
+        setting: LimitSetting,
+    },
+    /// Sets the credit limit of the client
+    Credit {
+        amount: Amount,
+    },
+    Freeze {
+        freeze: Freeze,
+    },
+    Unfreeze {
+        freeze: Freeze,
+    },
+    /// Type handled by a registered `TransactionHandler`
+    Custom {
+        kind: CustomKind,
+        amount: Option<Amount>,
+        asset: Asset,
+    },
+}
+
+/// Columns of an input row, before they are checked against the type
+#[derive(Deserialize)]
+pub struct RawRow {
+    #[serde(rename = "type")]
+    pub kind: TransactionType,
+    pub client: ClientId,
+    pub tx: TransactionId,
+    #[serde(default)]
+    pub amount: Option<Amount>,
+    #[serde(default, alias = "currency")]
+    pub asset: Option<Asset>,
+    #[serde(default)]
+    pub destination: Option<ClientId>,
+    #[serde(default)]
+    pub reason: Option<Reason>,
+    #[serde(default)]
+    pub timestamp: Option<Timestamp>,
+    #[serde(default)]
+    pub expires: Option<Timestamp>,
+    #[serde(default)]
+    pub limit: Option<LimitSetting>,
+    #[serde(default)]
+    pub freeze: Option<Freeze>,
+}
+
+impl RawRow {
+    fn required<T>(&self, value: Option<T>, field: &'static str) -> Result<T, RowError> {
+        value.ok_or(RowError::MissingField(self.kind, field))
+    }
+
+    fn absent<T>(&self, value: Option<T>, field: &'static str) -> Result<(), RowError> {
+        match value {
+            Some(_) => Err(RowError::UnexpectedField(self.kind, field)),
+            None => Ok(()),
+        }
+    }
+
+    /// Columns used by the deposit-like types
+    fn funds(&self) -> Result<(Amount, Asset), RowError> {
+        Ok((
+            self.required(self.amount, "amount")?,
+            self.asset.unwrap_or_default(),
+        ))
+    }
+
+    /// Rows referencing another transaction act on its asset
+    fn reference(&self) -> Result<Option<Amount>, RowError> {
+        self.absent(self.asset, "asset")?;
+        Ok(self.amount)
+    }
+}
+
+impl TryFrom<RawRow> for Row {
+    type Error = RowError;
+
+    fn try_from(raw: RawRow) -> Result<Self, Self::Error> {
+        if raw.kind != TransactionType::TRANSFER {
+            raw.absent(raw.destination, "destination")?;
+        }
+        if raw.kind != TransactionType::AUTHORIZE {
+            raw.absent(raw.expires, "expires")?;
+        }
+        if raw.kind != TransactionType::LIMIT {
+            raw.absent(raw.limit, "limit")?;
+        }
+        if !matches!(
+            raw.kind,
+            TransactionType::FREEZE | TransactionType::UNFREEZE
+        ) {
+            raw.absent(raw.freeze, "freeze")?;
+        }
+
+        let action = match raw.kind {
+            TransactionType::DEPOSIT => {
+                let (amount, asset) = raw.funds()?;
+                Action::Deposit { amount, asset }
+            }
+            TransactionType::WITHDRAWAL => {
+                let (amount, asset) = raw.funds()?;
+                Action::Withdrawal { amount, asset }
+            }
+            TransactionType::TRANSFER => {
+                let (amount, asset) = raw.funds()?;
+                let destination = raw.required(raw.destination, "destination")?;
+                Action::Transfer {
+                    amount,
+                    asset,
+                    destination,
+                }
+            }
+            TransactionType::AUTHORIZE => {
+                let (amount, asset) = raw.funds()?;
+                Action::Authorize {
+                    amount,
+                    asset,
+                    expires: raw.expires,
+                }
+            }
+            TransactionType::DISPUTE => Action::Dispute {
+                amount: raw.reference()?,
+            },
+            TransactionType::RESOLVE => Action::Resolve {
+                amount: raw.reference()?,
+            },
+            TransactionType::CHARGEBACK => Action::Chargeback {
+                amount: raw.reference()?,
+            },
+            TransactionType::CAPTURE => Action::Capture {
+                amount: raw.reference()?,
+            },
+            TransactionType::VOID => {
+                raw.reference()?;
+                raw.absent(raw.amount, "amount")?;
+                Action::Void
+            }
+            TransactionType::UNLOCK => {
+                raw.reference()?;
+                raw.absent(raw.amount, "amount")?;
+                raw.required(raw.reason, "reason")?;
+                Action::Unlock
+            }
+            TransactionType::FREEZE => {
+                raw.reference()?;
+                raw.absent(raw.amount, "amount")?;
+                raw.required(raw.reason, "reason")?;
+                Action::Freeze {
+                    freeze: raw.required(raw.freeze, "freeze")?,
+                }
+            }
+            TransactionType::UNFREEZE => {
+                raw.reference()?;
+                raw.absent(raw.amount, "amount")?;
+                Action::Unfreeze {
+                    freeze: raw.required(raw.freeze, "freeze")?,
+                }
+            }
+            TransactionType::CREDIT => {
+                raw.reference()?;
+                Action::Credit {
+                    amount: raw.required(raw.amount, "amount")?,
+                }
+            }
+            TransactionType::LIMIT => {
+                raw.reference()?;
+                raw.absent(raw.amount, "amount")?;
+                Action::Limit {
+                    setting: raw.required(raw.limit, "limit")?,
+                }
+            }
+            TransactionType::CUSTOM(kind) => Action::Custom {
+                kind,
+                amount: raw.amount,
+                asset: raw.asset.unwrap_or_default(),
+            },
+        };
+
+        Ok(Row {
+            client: raw.client,
+            tx: raw.tx,
+            reason: raw.reason,
+            timestamp: raw.timestamp,
+            action,
+        })
+    }
+}
+
+impl Row {
+    pub fn new(client: ClientId, tx: TransactionId, action: Action) -> Self {
+        Self {
+            client,
+            tx,
+            reason: None,
+            timestamp: None,
+            action,
+        }
+    }
+
+    pub fn with_reason(self, reason: Reason) -> Self {
+        Self {
+            reason: Some(reason),
+            ..self
+        }
+    }
+
+    pub fn with_timestamp(self, timestamp: Timestamp) -> Self {
+        Self {
+            timestamp: Some(timestamp),
+            ..self
+        }
+    }
+
+    pub fn kind(&self) -> TransactionType {
+        self.action.kind()
+    }
+
+    /// Receiving client, only transfers have one
+    pub fn destination(&self) -> Option<ClientId> {
+        match self.action {
+            Action::Transfer { destination, .. } => Some(destination),
+            _ => None,
+        }
+    }
+}
+
+impl Action {
+    pub fn kind(&self) -> TransactionType {
+        match self {
+            Action::Deposit { .. } => TransactionType::DEPOSIT,
+            Action::Withdrawal { .. } => TransactionType::WITHDRAWAL,
+            Action::Transfer { .. } => TransactionType::TRANSFER,
+            Action::Dispute { .. } => TransactionType::DISPUTE,
+            Action::Resolve { .. } => TransactionType::RESOLVE,
+            Action::Chargeback { .. } => TransactionType::CHARGEBACK,
+            Action::Authorize { .. } => TransactionType::AUTHORIZE,
+            Action::Capture { .. } => TransactionType::CAPTURE,
+            Action::Void => TransactionType::VOID,
+            Action::Unlock => TransactionType::UNLOCK,
+            Action::Limit { .. } => TransactionType::LIMIT,
+            Action::Credit { .. } => TransactionType::CREDIT,
+            Action::Freeze { .. } => TransactionType::FREEZE,
+            Action::Unfreeze { .. } => TransactionType::UNFREEZE,
+            Action::Custom { kind, .. } => TransactionType::CUSTOM(*kind),
+        }
+    }
+}
+
+/// Back to columns, e.g. to log the row
+impl From<Row> for RawRow {
+    fn from(row: Row) -> Self {
+        let mut raw = RawRow {
+            kind: row.kind(),
+            client: row.client,
+            tx: row.tx,
+            amount: None,
+            asset: None,
+            destination: None,
+            reason: row.reason,
+            timestamp: row.timestamp,
+            expires: None,
+            limit: None,
+            freeze: None,
+        };
+
+        match row.action {
+            Action::Deposit { amount, asset } | Action::Withdrawal { amount, asset } => {
+                raw.amount = Some(amount);
+                raw.asset = Some(asset);
+            }
+            Action::Transfer {
+                amount,
+                asset,
+                destination,
+            } => {
+                raw.amount = Some(amount);
+                raw.asset = Some(asset);
+                raw.destination = Some(destination);
+            }
+            Action::Authorize {
+                amount,
+                asset,
+                expires,
+            } => {
+                raw.amount = Some(amount);
+                raw.asset = Some(asset);
+                raw.expires = expires;
+            }
+            Action::Dispute { amount }
+            | Action::Resolve { amount }
+            | Action::Chargeback { amount }
+            | Action::Capture { amount } => raw.amount = amount,
+            Action::Credit { amount } => raw.amount = Some(amount),
+            Action::Limit { setting } => raw.limit = Some(setting),
+            Action::Freeze { freeze } | Action::Unfreeze { freeze } => raw.freeze = Some(freeze),
+            Action::Custom { amount, asset, .. } => {
+                raw.amount = amount;
+                raw.asset = Some(asset);
+            }
+            Action::Void | Action::Unlock => {}
+        }
+
+        raw
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Action, Row};
+    use crate::{decimal::Decimal, engine::LimitSetting};
+
+    fn parse(csv: &str) -> Result<Row, String> {
+        csv::Reader::from_reader(csv.as_bytes())
+            .deserialize()
+            .next()
+            .unwrap()
+            .map_err(|e| e.to_string())
+    }
+
+    #[test]
+    fn typed_rows() {
+        let row = parse("type,client,tx,amount\ndeposit,1,2,1.5\n").unwrap();
+        assert_eq!(
+            row.action,
+            Action::Deposit {
+                amount: "1.5".parse().unwrap(),
+                asset: Default::default()
+            }
+        );
+
+        let row = parse("type,client,tx,amount\ndispute,1,2,\n").unwrap();
+        assert_eq!(row.action, Action::Dispute { amount: None });
+
+        let row = parse("type,client,tx,amount\nresolve,1,2,3\n").unwrap();
+        assert_eq!(
+            row.action,
+            Action::Resolve {
+                amount: Some(Decimal::from(3))
+            }
+        );
+
+        let row = parse("type,client,tx,limit\nlimit,1,2,balance=100\n").unwrap();
+        assert_eq!(
+            row.action,
+            Action::Limit {
+                setting: LimitSetting::MaxBalance(Some(Decimal::from(100)))
+            }
+        );
+
+        let row = parse("type,client,tx,amount,destination\ntransfer,1,2,3,4\n").unwrap();
+        assert!(matches!(
+            row.action,
+            Action::Transfer { destination: 4, .. }
+        ));
+
+        let row = parse("type,client,tx,amount,asset\nloyalty,1,2,5,PTS\n").unwrap();
+        assert_eq!(
+            row.action,
+            Action::Custom {
+                kind: "loyalty".parse().unwrap(),
+                amount: Some(Decimal::from(5)),
+                asset: "PTS".parse().unwrap()
+            }
+        );
+    }
+
+    #[test]
+    fn malformed_rows() {
+        let missing = parse("type,client,tx,amount\ndeposit,1,2,\n").unwrap_err();
+        assert!(
+            missing.contains("DEPOSIT row is missing the amount"),
+            "{}",
+            missing
+        );
+
+        let missing = parse("type,client,tx\nwithdrawal,1,2\n").unwrap_err();
+        assert!(missing.contains("missing the amount"), "{}", missing);
+
+        let missing = parse("type,client,tx,amount\nunlock,1,2,\n").unwrap_err();
+        assert!(missing.contains("missing the reason"), "{}", missing);
+
+        let superfluous = parse("type,client,tx,amount\nvoid,1,2,5\n").unwrap_err();
+        assert!(
+            superfluous.contains("VOID row has an unexpected amount"),
+            "{}",
+            superfluous
+        );
+
+        let superfluous =
+            parse("type,client,tx,amount,destination\ndeposit,1,2,5,3\n").unwrap_err();
+        assert!(
+            superfluous.contains("unexpected destination"),
+            "{}",
+            superfluous
+        );
+
+        let superfluous = parse("type,client,tx,amount,asset\ndispute,1,2,,BTC\n").unwrap_err();
+        assert!(superfluous.contains("unexpected asset"), "{}", superfluous);
+
+        let missing = parse("type,client,tx,freeze\nfreeze,1,2,debits\n").unwrap_err();
+        assert!(missing.contains("missing the reason"), "{}", missing);
+
+        let missing = parse("type,client,tx,limit\nlimit,1,2,\n").unwrap_err();
+        assert!(missing.contains("missing the limit"), "{}", missing);
+
+        let superfluous =
+            parse("type,client,tx,amount,destination\nloyalty,1,2,5,3\n").unwrap_err();
+        assert!(
+            superfluous.contains("LOYALTY row has an unexpected destination"),
+            "{}",
+            superfluous
+        );
+
+        assert!(parse("type,client,tx,amount\ndeposit,1,2,abc\n").is_err());
+        assert!(parse("type,client,tx,amount\nloyalty!,1,2,5\n").is_err());
+        assert!(parse("type,client,tx,limit\nlimit,1,2,credit=5\n").is_err());
+    }
+}
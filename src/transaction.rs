@@ -1,20 +1,118 @@
 use serde::{Deserialize, Serialize};
+use std::{borrow::Cow, fmt::Display, str::FromStr};
 
-use crate::{client::ClientId, decimal::Decimal, errors::EngineError};
+use crate::{
+    asset::Asset,
+    client::{ClientId, Reason},
+    decimal::Amount,
+    errors::{DecimalError, EngineError},
+    row::{Action, Row},
+    short_code,
+};
 
 pub type TransactionId = u32;
+/// Seconds since the Unix epoch
+pub type Timestamp = u64;
 
-#[derive(Debug, Deserialize, Serialize, PartialEq, Eq, Clone, Copy)]
-#[serde(rename_all = "lowercase")]
+short_code!(CustomKind, 16, EngineError::InvalidTransactionType);
+
+/**
+ * Input type names are lowercase. Names the engine doesn't know are custom
+ * types, which only execute once a handler was registered for them.
+ */
+#[derive(Debug, PartialEq, Eq, Hash, Clone, Copy)]
 pub enum TransactionType {
     DEPOSIT,
     WITHDRAWAL,
     DISPUTE,
     RESOLVE,
     CHARGEBACK,
+    TRANSFER,
+    UNLOCK,
+    AUTHORIZE,
+    CAPTURE,
+    VOID,
+    LIMIT,
+    CREDIT,
+    FREEZE,
+    UNFREEZE,
+    CUSTOM(CustomKind),
 }
 
-#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+impl TransactionType {
+    pub fn name(&self) -> &str {
+        match self {
+            TransactionType::DEPOSIT => "deposit",
+            TransactionType::WITHDRAWAL => "withdrawal",
+            TransactionType::DISPUTE => "dispute",
+            TransactionType::RESOLVE => "resolve",
+            TransactionType::CHARGEBACK => "chargeback",
+            TransactionType::TRANSFER => "transfer",
+            TransactionType::UNLOCK => "unlock",
+            TransactionType::AUTHORIZE => "authorize",
+            TransactionType::CAPTURE => "capture",
+            TransactionType::VOID => "void",
+            TransactionType::LIMIT => "limit",
+            TransactionType::CREDIT => "credit",
+            TransactionType::FREEZE => "freeze",
+            TransactionType::UNFREEZE => "unfreeze",
+            TransactionType::CUSTOM(kind) => kind.as_str(),
+        }
+    }
+}
+
+impl FromStr for TransactionType {
+    type Err = EngineError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Ok(match s {
+            "deposit" => TransactionType::DEPOSIT,
+            "withdrawal" => TransactionType::WITHDRAWAL,
+            "dispute" => TransactionType::DISPUTE,
+            "resolve" => TransactionType::RESOLVE,
+            "chargeback" => TransactionType::CHARGEBACK,
+            "transfer" => TransactionType::TRANSFER,
+            "unlock" | "reactivate" => TransactionType::UNLOCK,
+            "authorize" => TransactionType::AUTHORIZE,
+            "capture" => TransactionType::CAPTURE,
+            "void" => TransactionType::VOID,
+            "limit" => TransactionType::LIMIT,
+            "credit" => TransactionType::CREDIT,
+            "freeze" => TransactionType::FREEZE,
+            "unfreeze" => TransactionType::UNFREEZE,
+            "" => return Err(EngineError::InvalidTransactionType),
+            _ => TransactionType::CUSTOM(s.parse()?),
+        })
+    }
+}
+
+impl Display for TransactionType {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+impl Serialize for TransactionType {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: serde::Serializer,
+    {
+        serializer.serialize_str(self.name())
+    }
+}
+
+impl<'de> Deserialize<'de> for TransactionType {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: serde::Deserializer<'de>,
+    {
+        let s = <Cow<str>>::deserialize(deserializer)?;
+        s.parse().map_err(serde::de::Error::custom)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
 pub enum TransactionDisputeStatus {
     NONE,
     DISPUTED,
@@ -27,41 +125,255 @@ impl TransactionDisputeStatus {
     }
 }
 
-#[derive(Debug, Serialize, Deserialize, Clone, Copy)]
+/**
+ * How much of a transaction is currently disputed and how much was already
+ * resolved or charged back. Resolved funds can be disputed again.
+ *
+ * When a dispute could only hold part of the amount, the rest is the
+ * `shortfall`. Charging back a shortfall turns it into a `receivable`, money
+ * the client owes but that never was on the account.
+ */
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DisputedAmounts {
+    pub disputed: Amount,
+    pub resolved: Amount,
+    pub charged_back: Amount,
+    pub shortfall: Amount,
+    pub receivable: Amount,
+}
+
+impl Default for DisputedAmounts {
+    fn default() -> Self {
+        Self {
+            disputed: Amount::zero(),
+            resolved: Amount::zero(),
+            charged_back: Amount::zero(),
+            shortfall: Amount::zero(),
+            receivable: Amount::zero(),
+        }
+    }
+}
+
+/**
+ * Record of a transaction that moved funds, as stored by the engine so that
+ * later rows can reference it, along with its dispute state.
+ */
+#[derive(Debug, Clone, Copy)]
 pub struct Transaction {
-    #[serde(rename = "type")]
     pub kind: TransactionType,
     pub client: ClientId,
     pub tx: TransactionId,
-    #[serde(default = "Decimal::zero")]
-    pub amount: Decimal<4>,
+    pub amount: Amount,
+    pub asset: Asset,
+    /// Receiving client, only used by transfers
+    pub destination: Option<ClientId>,
+    pub reason: Option<Reason>,
+    pub timestamp: Option<Timestamp>,
+    /// When an authorization stops holding funds
+    pub expires: Option<Timestamp>,
 
-    #[serde(skip)]
-    #[serde(default = "TransactionDisputeStatus::default")]
     pub dispute_status: TransactionDisputeStatus,
+    pub disputed_amounts: DisputedAmounts,
+    /// Fee charged along with the transaction
+    pub fee: Amount,
 }
 
 impl Transaction {
-    pub fn new(
-        kind: TransactionType,
-        client: ClientId,
-        tx: TransactionId,
-        amount: Decimal<4>,
-    ) -> Self {
+    pub fn new(kind: TransactionType, client: ClientId, tx: TransactionId, amount: Amount) -> Self {
         Self {
-            kind: kind,
-            client: client,
-            tx: tx,
-            amount: amount,
+            kind,
+            client,
+            tx,
+            amount,
+            asset: Asset::default(),
+            destination: None,
+            reason: None,
+            timestamp: None,
+            expires: None,
             dispute_status: TransactionDisputeStatus::default(),
+            disputed_amounts: DisputedAmounts::default(),
+            fee: Amount::zero(),
         }
     }
 
-    pub fn assure_status(&self, status: TransactionDisputeStatus) -> Result<(), EngineError> {
-        if self.dispute_status == status {
-            Ok(())
-        } else {
-            Err(EngineError::TransactionInvalidStatus(self.tx))
+    pub fn with_asset(self, asset: Asset) -> Self {
+        Self { asset, ..self }
+    }
+
+    pub fn with_destination(self, destination: ClientId) -> Self {
+        Self {
+            destination: Some(destination),
+            ..self
+        }
+    }
+
+    pub fn with_reason(self, reason: Reason) -> Self {
+        Self {
+            reason: Some(reason),
+            ..self
+        }
+    }
+
+    pub fn with_timestamp(self, timestamp: Timestamp) -> Self {
+        Self {
+            timestamp: Some(timestamp),
+            ..self
+        }
+    }
+
+    pub fn with_expiry(self, expires: Timestamp) -> Self {
+        Self {
+            expires: Some(expires),
+            ..self
+        }
+    }
+
+    /// Record of a row that moves funds, `None` for the other rows
+    pub fn from_row(row: &Row) -> Option<Self> {
+        let (amount, asset) = match row.action {
+            Action::Deposit { amount, asset }
+            | Action::Withdrawal { amount, asset }
+            | Action::Transfer { amount, asset, .. }
+            | Action::Authorize { amount, asset, .. } => (amount, asset),
+            Action::Custom {
+                amount: Some(amount),
+                asset,
+                ..
+            } => (amount, asset),
+            _ => return None,
+        };
+
+        let mut transaction =
+            Transaction::new(row.kind(), row.client, row.tx, amount).with_asset(asset);
+        transaction.destination = row.destination();
+        transaction.reason = row.reason;
+        transaction.timestamp = row.timestamp;
+        if let Action::Authorize { expires, .. } = row.action {
+            transaction.expires = expires;
         }
+
+        Some(transaction)
+    }
+
+    /// Whether `row` is the one this record was made of, ignoring the
+    /// dispute state
+    pub fn same_payload(&self, row: &Row) -> bool {
+        Transaction::from_row(row).is_some_and(|other| {
+            self.kind == other.kind
+                && self.client == other.client
+                && self.tx == other.tx
+                && self.amount == other.amount
+                && self.asset == other.asset
+                && self.destination == other.destination
+                && self.reason == other.reason
+                && self.timestamp == other.timestamp
+                && self.expires == other.expires
+        })
+    }
+
+    /// Part of the undisputed amount that a dispute row refers to, all that
+    /// is left if no amount is given.
+    pub fn dispute_amount(&self, requested: Option<Amount>) -> Result<Amount, EngineError> {
+        let d = self.disputed_amounts;
+        let remaining = self
+            .amount
+            .checked_sub(d.disputed)
+            .and_then(|r| r.checked_sub(d.charged_back))
+            .map_err(|_| EngineError::TransactionInvalidStatus(self.tx))?;
+
+        self.partial_amount(requested, remaining)
+    }
+
+    /// Part of the disputed amount that a resolve or chargeback row refers
+    /// to, all of it if no amount is given.
+    pub fn settle_amount(&self, requested: Option<Amount>) -> Result<Amount, EngineError> {
+        self.partial_amount(requested, self.disputed_amounts.disputed)
+    }
+
+    pub fn record_dispute(&mut self, amount: Amount) -> Result<(), DecimalError> {
+        self.disputed_amounts.disputed = self.disputed_amounts.disputed.checked_add(amount)?;
+        self.update_dispute_status();
+        Ok(())
+    }
+
+    /// Part of a disputed `amount` that could not be held
+    pub fn record_shortfall(&mut self, amount: Amount) -> Result<(), DecimalError> {
+        self.disputed_amounts.shortfall = self.disputed_amounts.shortfall.checked_add(amount)?;
+        Ok(())
+    }
+
+    /// Splits a settled amount into its held part and its shortfall part,
+    /// held funds are settled first.
+    pub fn split_shortfall(&self, amount: Amount) -> Result<(Amount, Amount), DecimalError> {
+        let d = self.disputed_amounts;
+        let held = d.disputed.checked_sub(d.shortfall)?.min(amount);
+
+        Ok((held, amount.checked_sub(held)?))
+    }
+
+    pub fn record_resolved_shortfall(&mut self, amount: Amount) -> Result<(), DecimalError> {
+        self.disputed_amounts.shortfall = self.disputed_amounts.shortfall.checked_sub(amount)?;
+        Ok(())
+    }
+
+    pub fn record_charged_back_shortfall(&mut self, amount: Amount) -> Result<(), DecimalError> {
+        let d = self.disputed_amounts;
+        self.disputed_amounts = DisputedAmounts {
+            shortfall: d.shortfall.checked_sub(amount)?,
+            receivable: d.receivable.checked_add(amount)?,
+            ..d
+        };
+        Ok(())
+    }
+
+    pub fn record_resolve(&mut self, amount: Amount) -> Result<(), DecimalError> {
+        let d = self.disputed_amounts;
+        self.disputed_amounts = DisputedAmounts {
+            disputed: d.disputed.checked_sub(amount)?,
+            resolved: d.resolved.checked_add(amount)?,
+            ..d
+        };
+        self.update_dispute_status();
+        Ok(())
+    }
+
+    pub fn record_chargeback(&mut self, amount: Amount) -> Result<(), DecimalError> {
+        let d = self.disputed_amounts;
+        self.disputed_amounts = DisputedAmounts {
+            disputed: d.disputed.checked_sub(amount)?,
+            charged_back: d.charged_back.checked_add(amount)?,
+            ..d
+        };
+        self.update_dispute_status();
+        Ok(())
+    }
+
+    fn partial_amount(
+        &self,
+        requested: Option<Amount>,
+        limit: Amount,
+    ) -> Result<Amount, EngineError> {
+        match requested {
+            Some(requested) if requested < Amount::zero() => {
+                Err(EngineError::NegativeAmount(requested))
+            }
+            _ if limit <= Amount::zero() => Err(EngineError::TransactionInvalidStatus(self.tx)),
+            None => Ok(limit),
+            Some(requested) if requested > limit => {
+                Err(EngineError::DisputeAmountTooLarge(self.tx, limit))
+            }
+            Some(requested) => Ok(requested),
+        }
+    }
+
+    fn update_dispute_status(&mut self) {
+        let d = self.disputed_amounts;
+        self.dispute_status = if d.disputed > Amount::zero() {
+            TransactionDisputeStatus::DISPUTED
+        } else if d.charged_back > Amount::zero() {
+            TransactionDisputeStatus::REVERSED
+        } else {
+            TransactionDisputeStatus::NONE
+        };
     }
 }